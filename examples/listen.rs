@@ -35,7 +35,7 @@ pub fn main() {
         let buf = &buf[..n];
         println!("{:?}\n{:?}", from, buf);
 
-        let (_, msg) = match dns::Message::<32, 32, 8>::parse(buf) {
+        let msg = match dns::Message::<32, 32, 8>::parse(buf) {
             Ok(v) => v,
             Err(e) => {
                 println!("ERROR: {:?}", e);
@@ -1,5 +1,5 @@
 use std::io::ErrorKind;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
 use std::time::{Duration, Instant};
 
 use opslag::{Cast, Input, Output, Server, ServiceInfo, Time};
@@ -7,7 +7,6 @@ use socket2::{Domain, Type};
 
 const MDNS_PORT: u16 = 5353;
 const GROUP_ADDR_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
-const GROUP_SOCK_V4: SocketAddrV4 = SocketAddrV4::new(GROUP_ADDR_V4, MDNS_PORT);
 const ANY_MDNS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), MDNS_PORT);
 
 pub fn main() {
@@ -77,17 +76,24 @@ pub fn main() {
     let mut input = Input::Timeout(next_timeout);
 
     loop {
+        // Anything but Output::Timeout might be followed by more output,
+        // in which case we come straight back with Input::Timeout.
+        next_timeout = now();
+
         match server.handle(input, &mut output) {
             Output::Packet(n, cast) => {
                 // Send a packet to the give destination.
                 let to_send = &output[..n];
 
                 let target = match cast {
-                    Cast::Multi { .. } => SocketAddr::V4(GROUP_SOCK_V4),
+                    Cast::Multi { target, .. } => target,
                     Cast::Uni { target, .. } => target,
                 };
 
-                sock.send_to(to_send, target).unwrap();
+                // The socket is IPv4 only.
+                if target.is_ipv4() {
+                    sock.send_to(to_send, target).unwrap();
+                }
             }
             Output::Timeout(time) => {
                 // Next time the server expects a handle(Input::Timeout).
@@ -97,6 +103,26 @@ pub fn main() {
                 // A discovered remote service.
                 println!("Remote: {:#?}", service);
             }
+            Output::RemoteLost(service) => {
+                // A discovered remote service went away.
+                println!("Lost: {:#?}", service);
+            }
+            Output::Renamed(service) => {
+                // Our instance name was taken, and the service got a new one.
+                println!("Renamed: {:#?}", service);
+            }
+            Output::ServiceType(service_type) => {
+                // A service type found on the network.
+                println!("Service type: {}", service_type);
+            }
+            Output::Resolved(hostname, ip) => {
+                // An address of a host name we resolve.
+                println!("Resolved: {} {}", hostname, ip);
+            }
+            Output::Overflow(needed) => {
+                // The output buffer is too small for a packet.
+                println!("Overflow, needs: {}", needed);
+            }
         }
 
         // Check how long until the next timeout.
@@ -0,0 +1,426 @@
+use core::net::IpAddr;
+
+use crate::dns::{Answer, QClass, QType, Query, Record};
+use crate::server::is_same_network;
+use crate::time::Time;
+use crate::vec::Vec;
+use crate::ServiceInfo;
+
+/// Cache of discovered remote services.
+///
+/// Keyed by instance name. Keeps track of the TTL of the records that make up
+/// each service, and whether the service has been reported to the user.
+pub(crate) struct Cache<const LLEN: usize, const CLEN: usize, const SUBLEN: usize> {
+    entries: Vec<Entry<LLEN, SUBLEN>, CLEN>,
+}
+
+struct Entry<const LLEN: usize, const SUBLEN: usize> {
+    info: ServiceInfo<'static, LLEN, SUBLEN>,
+    /// Address the service was last heard from.
+    from: IpAddr,
+    ptr: Ttl,
+    srv: Ttl,
+    addr: Ttl,
+    /// New or changed, and not yet reported.
+    changed: bool,
+    /// Expired or said goodbye, and not yet reported.
+    lost: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Ttl {
+    received: Time,
+    secs: u32,
+    /// Number of refresh queries sent for the record.
+    refreshes: u8,
+}
+
+/// Refresh queries are sent at 80%, 85%, 90% and 95% of the TTL (RFC 6762 5.2).
+const REFRESH_PERCENT: [u64; 4] = [80, 85, 90, 95];
+
+pub(crate) enum CacheEvent<const LLEN: usize, const SUBLEN: usize> {
+    Changed(ServiceInfo<'static, LLEN, SUBLEN>),
+    Lost(ServiceInfo<'static, LLEN, SUBLEN>),
+}
+
+impl Ttl {
+    fn new(received: Time, secs: u32) -> Self {
+        // A TTL of 0 is a goodbye. RFC 6762 10.1 says to keep the record
+        // for one more second, in case the goodbye was a mistake.
+        // There is no point refreshing a record that said goodbye.
+        let refreshes = if secs == 0 {
+            REFRESH_PERCENT.len() as u8
+        } else {
+            0
+        };
+
+        Ttl {
+            received,
+            secs: secs.max(1),
+            refreshes,
+        }
+    }
+
+    fn expires(&self) -> Time {
+        self.received + self.secs as u64 * 1000
+    }
+
+    /// Remaining TTL in seconds.
+    fn remaining(&self, now: Time) -> u32 {
+        (now.millis_until(self.expires()) / 1000) as u32
+    }
+
+    fn next_refresh(&self) -> Option<Time> {
+        let percent = REFRESH_PERCENT.get(self.refreshes as usize)?;
+        Some(self.received + self.secs as u64 * 10 * percent)
+    }
+
+    /// Whether a refresh is due. Skips past all refresh points that are due.
+    fn take_refresh(&mut self, now: Time) -> bool {
+        let mut due = false;
+        while self.next_refresh().map(|t| now >= t).unwrap_or(false) {
+            self.refreshes += 1;
+            due = true;
+        }
+        due
+    }
+}
+
+impl<const LLEN: usize, const SUBLEN: usize> Entry<LLEN, SUBLEN> {
+    fn expires(&self) -> Time {
+        self.ptr
+            .expires()
+            .min(self.srv.expires())
+            .min(self.addr.expires())
+    }
+
+    fn next_refresh(&self) -> Option<Time> {
+        [self.ptr, self.srv, self.addr]
+            .iter()
+            .filter_map(|t| t.next_refresh())
+            .min()
+    }
+}
+
+impl<const LLEN: usize, const CLEN: usize, const SUBLEN: usize> Cache<LLEN, CLEN, SUBLEN> {
+    pub fn new() -> Self {
+        Cache {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Insert a complete service found in a response.
+    ///
+    /// The TTLs are set by a following [`Cache::refresh`] with the answers the service
+    /// came from. Returns `false` if the service doesn't fit in the cache.
+    pub fn insert(
+        &mut self,
+        info: &ServiceInfo<'_, LLEN, SUBLEN>,
+        from: IpAddr,
+        now: Time,
+    ) -> bool {
+        let existing = self
+            .entries
+            .iter_mut()
+            .find(|e| e.info.instance_name() == info.instance_name());
+
+        if let Some(entry) = existing {
+            let Some(mut owned) = info.try_to_owned() else {
+                return false;
+            };
+            owned.keep_subtypes(&entry.info);
+            if entry.info != owned {
+                entry.info = owned;
+                entry.changed = true;
+            }
+            entry.from = from;
+            entry.lost = false;
+            return true;
+        }
+
+        let Some(owned) = info.try_to_owned() else {
+            return false;
+        };
+
+        let ttl = Ttl::new(now, 0);
+
+        let entry = Entry {
+            info: owned,
+            from,
+            ptr: ttl,
+            srv: ttl,
+            addr: ttl,
+            changed: true,
+            lost: false,
+        };
+
+        self.entries.push(entry).is_ok()
+    }
+
+    /// Update the TTLs of cached services from the records in a response.
+    ///
+    /// Records that differ from what is cached don't refresh anything. Such
+    /// changes are picked up via [`Cache::insert`] once complete.
+    pub fn refresh<'b>(&mut self, answers: impl Iterator<Item = &'b Answer<'b, LLEN>>, now: Time) {
+        for answer in answers {
+            let ttl = Ttl::new(now, answer.ttl);
+
+            for entry in self.entries.iter_mut() {
+                let info = &entry.info;
+
+                match &answer.record {
+                    Record::PTR(ptr)
+                        if (answer.name == info.service_type()
+                            || info.subtypes().any(|s| s == answer.name))
+                            && ptr.name == info.instance_name() =>
+                    {
+                        entry.ptr = ttl;
+                    }
+                    Record::SRV(srv)
+                        if answer.name == info.instance_name()
+                            && srv.port == info.port()
+                            && srv.target == info.hostname() =>
+                    {
+                        entry.srv = ttl;
+                    }
+                    Record::A(a)
+                        if answer.name == info.hostname()
+                            && info.ip_addresses().any(|ip| ip == IpAddr::V4(a.address)) =>
+                    {
+                        entry.addr = ttl;
+                    }
+                    Record::AAAA(aaaa)
+                        if answer.name == info.hostname()
+                            && info.ip_addresses().any(|ip| ip == IpAddr::V6(aaaa.address)) =>
+                    {
+                        entry.addr = ttl;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Mark services with expired records as lost.
+    pub fn expire(&mut self, now: Time) {
+        for entry in self.entries.iter_mut() {
+            if !entry.lost && now >= entry.expires() {
+                debug!("Remote expired: {:?}", entry.info.instance_name());
+                entry.lost = true;
+            }
+        }
+    }
+
+    /// Mark services heard from on the network of `ip`/`netmask` as lost.
+    pub fn remove_network(&mut self, ip: IpAddr, netmask: IpAddr) {
+        for entry in self.entries.iter_mut() {
+            if !entry.lost && is_same_network(ip, netmask, entry.from) {
+                debug!("Remote on lost network: {:?}", entry.info.instance_name());
+                entry.lost = true;
+            }
+        }
+    }
+
+    /// The next time some service in the cache expires.
+    pub fn next_expiry(&self) -> Option<Time> {
+        self.entries
+            .iter()
+            .filter(|e| !e.lost)
+            .map(|e| e.expires())
+            .min()
+    }
+
+    /// The next time a record in the cache should be refreshed.
+    pub fn next_refresh(&self) -> Option<Time> {
+        self.entries
+            .iter()
+            .filter(|e| !e.lost)
+            .filter_map(|e| e.next_refresh())
+            .min()
+    }
+
+    /// Add queries for records of one service that are due for refresh.
+    ///
+    /// Returns the address of the remote, or `None` if nothing is due.
+    pub fn poll_refresh<'c, const QLEN: usize>(
+        &'c mut self,
+        now: Time,
+        queries: &mut Vec<Query<'c, LLEN>, QLEN>,
+    ) -> Option<IpAddr> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .filter(|e| !e.lost)
+            .find(|e| e.next_refresh().map(|t| now >= t).unwrap_or(false))?;
+
+        let info = &entry.info;
+
+        if entry.ptr.take_refresh(now) {
+            let _ = queries.push(Query {
+                name: info.service_type(),
+                qtype: QType::PTR,
+                qclass: QClass::IN,
+                unicast_response: false,
+            });
+        }
+
+        if entry.srv.take_refresh(now) {
+            let _ = queries.push(Query {
+                name: info.instance_name(),
+                qtype: QType::SRV,
+                qclass: QClass::IN,
+                unicast_response: false,
+            });
+        }
+
+        if entry.addr.take_refresh(now) {
+            let has_v4 = info.ip_addresses().any(|ip| ip.is_ipv4());
+            let has_v6 = info.ip_addresses().any(|ip| ip.is_ipv6());
+
+            for (qtype, present) in [(QType::A, has_v4), (QType::AAAA, has_v6)] {
+                if present {
+                    let _ = queries.push(Query {
+                        name: info.hostname(),
+                        qtype,
+                        qclass: QClass::IN,
+                        unicast_response: false,
+                    });
+                }
+            }
+        }
+
+        Some(info.ip_address())
+    }
+
+    /// Add known answers for the queries (RFC 6762 7.1).
+    ///
+    /// Only records with more than half their TTL remaining are included, since
+    /// the responder would otherwise answer anyway. The first `skip` answers are
+    /// left out, for continuing in another packet. Returns true if there are more
+    /// answers than fit in `answers`.
+    pub fn known_answers<'c, const ALEN: usize>(
+        &'c self,
+        now: Time,
+        queries: &[Query<'_, LLEN>],
+        skip: usize,
+        answers: &mut Vec<Answer<'c, LLEN>, ALEN>,
+    ) -> bool {
+        let candidates = || {
+            self.entries
+                .iter()
+                .filter(|e| !e.lost)
+                .flat_map(move |entry| {
+                    let info: &ServiceInfo<'c, LLEN, SUBLEN> = &entry.info;
+
+                    info.ptr_answers()
+                        .map(|a| (a, entry.ptr))
+                        .chain([(info.srv_answer(false), entry.srv)])
+                        .chain(info.ip_answers(false).map(|a| (a, entry.addr)))
+                })
+                .filter_map(move |(mut answer, ttl)| {
+                    let is_asked = queries.iter().any(|q| {
+                        q.name == answer.name && (q.qtype == answer.atype || q.qtype == QType::Any)
+                    });
+
+                    let remaining = ttl.remaining(now);
+
+                    if !is_asked || remaining * 2 <= ttl.secs {
+                        return None;
+                    }
+
+                    answer.ttl = remaining;
+                    Some(answer)
+                })
+        };
+
+        let mut count = 0;
+
+        for (idx, answer) in candidates().enumerate() {
+            // Records shared between services, like the address of a host, are only
+            // sent once. Compare to all candidates, so `skip` is the same for every
+            // packet of a truncated query.
+            if candidates().take(idx).any(|a| a == answer) {
+                continue;
+            }
+
+            count += 1;
+
+            if count > skip && answers.push(answer).is_err() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Whether there are changes not yet reported.
+    pub fn has_events(&self) -> bool {
+        self.entries.iter().any(|e| e.changed || e.lost)
+    }
+
+    /// Take the next unreported change. Lost services are removed from the cache.
+    pub fn poll_event(&mut self) -> Option<CacheEvent<LLEN, SUBLEN>> {
+        if let Some(idx) = self.entries.iter().position(|e| e.lost) {
+            let entry = self.entries.remove(idx);
+            return Some(CacheEvent::Lost(entry.info));
+        }
+
+        let entry = self.entries.iter_mut().find(|e| e.changed)?;
+        entry.changed = false;
+
+        Some(CacheEvent::Changed(entry.info.clone()))
+    }
+}
+
+#[cfg(all(feature = "std", test))]
+mod test {
+    use super::*;
+    use crate::dns::Label;
+
+    #[test]
+    fn shared_known_answer_is_counted_once() {
+        let mut cache: Cache<4, 4, 0> = Cache::new();
+        let now = Time::from_millis(0);
+
+        // Two services on the same host share the A record.
+        for name in ["a", "b"] {
+            let info = ServiceInfo::new(
+                "_test._tcp.local",
+                name,
+                "other.local",
+                [192, 168, 0, 2],
+                [255, 255, 255, 0],
+                1234,
+            );
+            assert!(cache.insert(&info, info.ip_address(), now));
+            let answers: std::vec::Vec<_> = info.as_answers(true).collect();
+            cache.refresh(answers.iter(), now);
+        }
+
+        let queries = [
+            Query {
+                name: Label::new("_test._tcp.local"),
+                qtype: QType::PTR,
+                qclass: QClass::IN,
+                unicast_response: false,
+            },
+            Query {
+                name: Label::new("other.local"),
+                qtype: QType::A,
+                qclass: QClass::IN,
+                unicast_response: false,
+            },
+        ];
+
+        let mut all: Vec<Answer<'_, 4>, 8> = Vec::new();
+        assert!(!cache.known_answers(now, &queries, 0, &mut all));
+        let qtypes: std::vec::Vec<_> = all.iter().map(|a| a.atype).collect();
+        assert_eq!(qtypes, [QType::PTR, QType::A, QType::PTR]);
+
+        // A continuation after two answers has only the one left.
+        let mut rest: Vec<Answer<'_, 4>, 8> = Vec::new();
+        assert!(!cache.known_answers(now, &queries, 2, &mut rest));
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0], all[2]);
+    }
+}
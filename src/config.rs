@@ -0,0 +1,126 @@
+/// Timing of the [`Server`][crate::Server].
+///
+/// All durations are in milliseconds. The defaults follow RFC 6762. Battery powered
+/// nodes might want longer intervals, and test rigs shorter.
+///
+/// ```
+/// use opslag::{Server, ServerConfig};
+///
+/// let config = ServerConfig {
+///     advertise_interval: 60_000,
+///     ..Default::default()
+/// };
+///
+/// let server: Server<4, 4, 4, 1, 10> = Server::new_with_config(std::iter::empty(), config);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Delay before probing for the services given to [`Server::new`][crate::Server::new].
+    ///
+    /// Defaults to 3000.
+    pub probe_delay: u64,
+
+    /// Max random delay before the first probe of a service, on top of
+    /// [`probe_delay`][Self::probe_delay] for the services given to
+    /// [`Server::new`][crate::Server::new] (RFC 6762 8.1).
+    ///
+    /// Defaults to 250.
+    pub probe_jitter: u64,
+
+    /// Number of announcements once a service is probed (RFC 6762 8.3).
+    ///
+    /// Defaults to 2.
+    pub announce_count: u8,
+
+    /// Time between the first and second announcement. It doubles for each further
+    /// announcement.
+    ///
+    /// Defaults to 1000.
+    pub announce_interval: u64,
+
+    /// Time between advertisements after the announcements.
+    ///
+    /// Defaults to 15000.
+    pub advertise_interval: u64,
+
+    /// Delay before the first query for the types of the services given to
+    /// [`Server::new`][crate::Server::new].
+    ///
+    /// Defaults to 5000.
+    pub query_delay: u64,
+
+    /// Time between the first and second query for a service type. It doubles for each
+    /// further query, up to [`query_interval_max`][Self::query_interval_max] (RFC 6762 5.2).
+    /// The backoff starts over for new query targets, and after address changes.
+    ///
+    /// Defaults to 1000.
+    pub query_interval: u64,
+
+    /// The max time between queries.
+    ///
+    /// Defaults to 3600000, i.e. one hour.
+    pub query_interval_max: u64,
+
+    /// Min random delay for the first query of a service type, and for responses with
+    /// records that other hosts might answer too (RFC 6762 5.2, 6).
+    ///
+    /// Defaults to 20.
+    pub jitter_min: u64,
+
+    /// Max random delay, see [`jitter_min`][Self::jitter_min].
+    ///
+    /// Defaults to 120.
+    pub jitter_max: u64,
+
+    /// Seed for the random delays.
+    ///
+    /// The local addresses and instance names are mixed into the seed, so nodes on
+    /// the default seed still differ. Nodes that can share both, like several
+    /// processes on one host that only query, should have different seeds, or they
+    /// end up sending at the same time anyway. Use a hardware random source, or
+    /// something unique like a MAC address.
+    ///
+    /// Defaults to 0.
+    pub seed: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            probe_delay: 3000,
+            probe_jitter: 250,
+            announce_count: 2,
+            announce_interval: 1000,
+            advertise_interval: 15_000,
+            query_delay: 5000,
+            query_interval: 1000,
+            query_interval_max: 3_600_000,
+            jitter_min: 20,
+            jitter_max: 120,
+            seed: 0,
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ServerConfig {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(
+            fmt,
+            "ServerConfig {{ probe_delay: {}, probe_jitter: {}, announce_count: {}, announce_interval: {}, \
+            advertise_interval: {}, query_delay: {}, query_interval: {}, \
+            query_interval_max: {}, jitter_min: {}, jitter_max: {}, seed: {} }}",
+            self.probe_delay,
+            self.probe_jitter,
+            self.announce_count,
+            self.announce_interval,
+            self.advertise_interval,
+            self.query_delay,
+            self.query_interval,
+            self.query_interval_max,
+            self.jitter_min,
+            self.jitter_max,
+            self.seed,
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use core::fmt;
+
+/// Error from parsing a DNS message.
+///
+/// The offset is the position in the message where parsing failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// The message ends before it should.
+    Truncated { offset: usize },
+    /// A name compression pointer points outside the message.
+    BadPointer { offset: usize },
+    /// Name compression pointers that go around in a loop, or nest too deep.
+    PointerLoop { offset: usize },
+    /// A name that is not UTF-8.
+    NotUtf8 { offset: usize },
+    /// More entries than the capacity given by the const generics.
+    Capacity { offset: usize, section: Section },
+    /// A record of a type that can't be parsed.
+    Unsupported { offset: usize, rtype: u16 },
+    /// Record data that doesn't match the record type, like an A record that isn't 4 bytes.
+    BadRecord { offset: usize, rtype: u16 },
+}
+
+/// Part of a DNS message that ran out of capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    /// The queries, `QLEN`.
+    Queries,
+    /// The answers, `ALEN`.
+    Answers,
+    /// The authority records, `NSLEN`.
+    Authorities,
+    /// The additional records, `ARLEN`.
+    Additionals,
+    /// The parts of a name, `LLEN`.
+    Label,
+}
+
+impl ParseError {
+    /// Position in the message where parsing failed.
+    pub fn offset(&self) -> usize {
+        match self {
+            ParseError::Truncated { offset }
+            | ParseError::BadPointer { offset }
+            | ParseError::PointerLoop { offset }
+            | ParseError::NotUtf8 { offset }
+            | ParseError::Capacity { offset, .. }
+            | ParseError::Unsupported { offset, .. }
+            | ParseError::BadRecord { offset, .. } => *offset,
+        }
+    }
+
+    /// Whether the message might parse with larger capacities.
+    pub fn is_capacity(&self) -> bool {
+        matches!(self, ParseError::Capacity { .. })
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Truncated { offset } => core::write!(f, "truncated message at {}", offset),
+            ParseError::BadPointer { offset } => core::write!(f, "bad name pointer at {}", offset),
+            ParseError::PointerLoop { offset } => {
+                core::write!(f, "name pointer loop at {}", offset)
+            }
+            ParseError::NotUtf8 { offset } => core::write!(f, "name not UTF-8 at {}", offset),
+            ParseError::Capacity { offset, section } => {
+                core::write!(f, "too many {:?} at {}", section, offset)
+            }
+            ParseError::Unsupported { offset, rtype } => {
+                core::write!(f, "unsupported record type {} at {}", rtype, offset)
+            }
+            ParseError::BadRecord { offset, rtype } => {
+                core::write!(f, "bad record of type {} at {}", rtype, offset)
+            }
+        }
+    }
+}
+
+impl core::error::Error for ParseError {}
+
+/// Error used by the nom parsers, turned into a [`ParseError`] once
+/// the offset into the message is known.
+#[derive(Debug)]
+pub(crate) struct Error<'a> {
+    input: &'a [u8],
+    kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ErrorKind {
+    Truncated,
+    BadPointer,
+    PointerLoop,
+    NotUtf8,
+    Capacity(Section),
+    Unsupported(u16),
+    BadRecord(u16),
+}
+
+pub(crate) type IResult<'a, T> = nom::IResult<&'a [u8], T, Error<'a>>;
+
+impl<'a> Error<'a> {
+    /// An error that stops the parsing.
+    pub(crate) fn failure(input: &'a [u8], kind: ErrorKind) -> nom::Err<Error<'a>> {
+        nom::Err::Failure(Error { input, kind })
+    }
+
+    /// Turn a nom error into a [`ParseError`]. The `context` is the entire message.
+    pub(crate) fn into_parse_error(error: nom::Err<Error<'a>>, context: &'a [u8]) -> ParseError {
+        let (input, kind) = match error {
+            nom::Err::Error(e) | nom::Err::Failure(e) => (e.input, e.kind),
+            nom::Err::Incomplete(_) => (&context[context.len()..], ErrorKind::Truncated),
+        };
+
+        // All parsing happens on subslices of the message.
+        let offset = (input.as_ptr() as usize)
+            .saturating_sub(context.as_ptr() as usize)
+            .min(context.len());
+
+        match kind {
+            ErrorKind::Truncated => ParseError::Truncated { offset },
+            ErrorKind::BadPointer => ParseError::BadPointer { offset },
+            ErrorKind::PointerLoop => ParseError::PointerLoop { offset },
+            ErrorKind::NotUtf8 => ParseError::NotUtf8 { offset },
+            ErrorKind::Capacity(section) => ParseError::Capacity { offset, section },
+            ErrorKind::Unsupported(rtype) => ParseError::Unsupported { offset, rtype },
+            ErrorKind::BadRecord(rtype) => ParseError::BadRecord { offset, rtype },
+        }
+    }
+}
+
+impl<'a> nom::error::ParseError<&'a [u8]> for Error<'a> {
+    fn from_error_kind(input: &'a [u8], _kind: nom::error::ErrorKind) -> Self {
+        // The nom parsers we use only fail on running out of input.
+        Error {
+            input,
+            kind: ErrorKind::Truncated,
+        }
+    }
+
+    fn append(_input: &'a [u8], _kind: nom::error::ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ParseError {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            ParseError::Truncated { offset } => {
+                defmt::write!(fmt, "ParseError::Truncated({})", offset)
+            }
+            ParseError::BadPointer { offset } => {
+                defmt::write!(fmt, "ParseError::BadPointer({})", offset)
+            }
+            ParseError::PointerLoop { offset } => {
+                defmt::write!(fmt, "ParseError::PointerLoop({})", offset)
+            }
+            ParseError::NotUtf8 { offset } => {
+                defmt::write!(fmt, "ParseError::NotUtf8({})", offset)
+            }
+            ParseError::Capacity { offset, section } => {
+                defmt::write!(fmt, "ParseError::Capacity({}, {:?})", offset, section)
+            }
+            ParseError::Unsupported { offset, rtype } => {
+                defmt::write!(fmt, "ParseError::Unsupported({}, {})", offset, rtype)
+            }
+            ParseError::BadRecord { offset, rtype } => {
+                defmt::write!(fmt, "ParseError::BadRecord({}, {})", offset, rtype)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Section {
+    fn format(&self, fmt: defmt::Formatter) {
+        let section = match self {
+            Section::Queries => "Queries",
+            Section::Answers => "Answers",
+            Section::Authorities => "Authorities",
+            Section::Additionals => "Additionals",
+            Section::Label => "Label",
+        };
+        defmt::write!(fmt, "Section::{=str}", section);
+    }
+}
@@ -1,6 +1,7 @@
 use core::fmt;
 use nom::number::complete::be_u16;
-use nom::IResult;
+
+use super::error::IResult;
 
 use crate::writer::Writer;
 
@@ -151,7 +152,7 @@ impl Flags {
         self.0 = (self.0 & !0x000F) | (rcode as u16 & 0x0F);
     }
 
-    pub fn parse(input: &[u8]) -> IResult<&[u8], Flags> {
+    pub(crate) fn parse(input: &[u8]) -> IResult<'_, Flags> {
         let (input, flags) = be_u16(input)?;
         Ok((input, Flags(flags)))
     }
@@ -1,14 +1,23 @@
+use core::cmp::Ordering;
 use core::fmt;
+use core::hash::{Hash, Hasher};
 use core::str;
 
 use nom::bytes::complete::take;
-use nom::error::make_error;
 use nom::number::complete::be_u8;
-use nom::IResult;
+
+use super::error::{Error, ErrorKind, IResult, Section};
 
 use crate::vec::Vec;
 use crate::writer::Writer;
 
+/// Max length of a single segment in a DNS label.
+pub const MAX_SEGMENT_LEN: usize = 63;
+
+/// Max length of a name on the wire, with the length of every segment and the
+/// terminating zero.
+pub const MAX_NAME_LEN: usize = 255;
+
 #[derive(Default, Clone)]
 pub struct Label<'a, const LLEN: usize> {
     items: Vec<LabelPart<'a>, LLEN>,
@@ -28,6 +37,15 @@ impl<'a> LabelPart<'a> {
         }
     }
 
+    /// The segments as they are on the wire. Unlike [`LabelPart::iter`], a segment
+    /// with a dot in it stays one segment.
+    fn segments(&self) -> LabelPartSegments<'a> {
+        match self {
+            LabelPart::Run(run) => LabelPartSegments::Run(run.segments()),
+            LabelPart::Str(lab) => LabelPartSegments::Str(lab.iter()),
+        }
+    }
+
     fn serialize<'b, const LK: usize>(&self, w: &mut Writer<'a, 'b, LK>, is_last: bool) {
         match self {
             LabelPart::Run(run) => run.serialize(w),
@@ -77,6 +95,13 @@ impl<'a> LabelRun<'a> {
             partial: None,
         }
     }
+
+    fn segments(&self) -> LabelRunSegments<'a> {
+        LabelRunSegments {
+            data: self.run,
+            context: self.context,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -96,6 +121,65 @@ impl<'a> LabelStr<'a> {
     }
 }
 
+/// A name that doesn't borrow anything.
+///
+/// Used for names that must outlive the packet they were parsed from, and for names
+/// the [`Server`][crate::Server] makes up itself, like renamed instances. The name is
+/// kept as on the wire, in a `heapless::Vec` without **alloc**. It compares equal to
+/// the borrowed [`Label`] it was copied from.
+#[derive(Clone)]
+pub struct OwnedLabel {
+    /// Length prefixed segments, with the terminating zero.
+    wire: Vec<u8, MAX_NAME_LEN>,
+}
+
+impl OwnedLabel {
+    /// Copy a name from its segments. Returns `None` if a segment is longer than
+    /// [`MAX_SEGMENT_LEN`], or the name is longer than [`MAX_NAME_LEN`].
+    pub(crate) fn from_segments<'s>(segments: impl Iterator<Item = &'s str>) -> Option<Self> {
+        let mut wire = Vec::new();
+        for segment in segments {
+            if segment.is_empty() || segment.len() > MAX_SEGMENT_LEN {
+                return None;
+            }
+            wire.push(segment.len() as u8).ok()?;
+            for b in segment.bytes() {
+                wire.push(b).ok()?;
+            }
+        }
+        // The limit is on the wire, also when the Vec could grow (RFC 1035 2.3.4).
+        if wire.len() >= MAX_NAME_LEN {
+            return None;
+        }
+        wire.push(0).ok()?;
+        Some(OwnedLabel { wire })
+    }
+
+    /// The name as a [`Label`] borrowing this one.
+    pub fn label<const LLEN: usize>(&self) -> Label<'_, LLEN> {
+        let mut label = Label::default();
+        // Only fails for LLEN 0, which can't hold any name.
+        let _ = label.items.push(LabelPart::Run(self.run()));
+        label
+    }
+
+    /// The segments of the name, split on dots like for [`Label::iter`].
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.run().iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    fn run(&self) -> LabelRun<'_> {
+        LabelRun {
+            run: &self.wire,
+            context: &self.wire,
+        }
+    }
+}
+
 impl<'a, const LLEN: usize> Label<'a, LLEN> {
     pub fn new(s: &'a str) -> Self {
         assert!(!s.ends_with('.'), "Labels must not end with: .");
@@ -114,7 +198,51 @@ impl<'a, const LLEN: usize> Label<'a, LLEN> {
         self.items.push(LabelPart::Str(LabelStr::new(part))).is_ok()
     }
 
-    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<&'a [u8], Self> {
+    /// Remove the first segment, like `_sub` of `_sub._http._tcp.local`.
+    pub(crate) fn pop_front(&mut self) {
+        let Some(first) = self.items.first_mut() else {
+            return;
+        };
+
+        let is_used_up = match first {
+            LabelPart::Run(run) => {
+                let len = run.run.first().copied().unwrap_or(0) as usize;
+                run.run = run.run.get(1 + len..).unwrap_or(&[]);
+                // Nothing left but the terminating 0.
+                run.run.len() < 2
+            }
+            LabelPart::Str(LabelStr(s)) => match s.split_once('.') {
+                Some((_, rest)) => {
+                    *s = rest;
+                    false
+                }
+                None => true,
+            },
+        };
+
+        if is_used_up {
+            self.items.remove(0);
+        }
+    }
+
+    /// Copy the label into one that doesn't borrow anything.
+    ///
+    /// Labels parsed from a packet borrow the packet buffer. The owned copy can be
+    /// kept after the buffer is reused, and still compares equal to the borrowed one.
+    ///
+    /// Returns `None` if a segment is longer than [`MAX_SEGMENT_LEN`], or the name
+    /// is longer than [`MAX_NAME_LEN`].
+    pub fn try_to_owned(&self) -> Option<OwnedLabel> {
+        OwnedLabel::from_segments(self.segments())
+    }
+
+    /// The segments as they are on the wire, where a segment can have a dot in it,
+    /// like an instance name.
+    pub(crate) fn segments(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.items.iter().flat_map(|part| part.segments())
+    }
+
+    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<'a, Self> {
         trace!("Label::parse start");
         assert!(!context.is_empty());
         let mut label = Label::default();
@@ -127,7 +255,7 @@ impl<'a, const LLEN: usize> Label<'a, LLEN> {
         context: &'a [u8],
         into: &mut Label<'a, LLEN>,
         recurse_limit: u8,
-    ) -> IResult<&'a [u8], ()> {
+    ) -> IResult<'a, ()> {
         let all = input;
         let mut input = input;
         let mut run_end = 0;
@@ -149,7 +277,7 @@ impl<'a, const LLEN: usize> Label<'a, LLEN> {
                     }))
                     .map_err(|_| {
                         warn!("Label::parse too many parts");
-                        nom::Err::Failure(make_error(input, nom::error::ErrorKind::TooLarge))
+                        Error::failure(input, ErrorKind::Capacity(Section::Label))
                     })?;
             }
 
@@ -170,18 +298,12 @@ impl<'a, const LLEN: usize> Label<'a, LLEN> {
                         offset,
                         context.len()
                     );
-                    return Err(nom::Err::Failure(make_error(
-                        input,
-                        nom::error::ErrorKind::LengthValue,
-                    )));
+                    return Err(Error::failure(input, ErrorKind::BadPointer));
                 };
 
                 if pointered.len() < 2 || pointered[..2] == input[..2] || recurse_limit == 0 {
                     warn!("Label::parse offset recurses",);
-                    return Err(nom::Err::Failure(make_error(
-                        input,
-                        nom::error::ErrorKind::LengthValue,
-                    )));
+                    return Err(Error::failure(input, ErrorKind::PointerLoop));
                 }
 
                 trace!("Label::parse ptr({}) after: {:?}", offset, into);
@@ -194,9 +316,7 @@ impl<'a, const LLEN: usize> Label<'a, LLEN> {
             let (new_input, label) = take(len)(new_input)?;
 
             // Verify it's correct utf8
-            str::from_utf8(label).map_err(|_| {
-                nom::Err::Failure(make_error(input, nom::error::ErrorKind::AlphaNumeric))
-            })?;
+            str::from_utf8(label).map_err(|_| Error::failure(input, ErrorKind::NotUtf8))?;
 
             input = new_input;
             run_end += len as usize;
@@ -207,10 +327,7 @@ impl<'a, const LLEN: usize> Label<'a, LLEN> {
 
     pub(crate) fn serialize<'b, const LK: usize>(&self, w: &mut Writer<'a, 'b, LK>) {
         let mut iter = self.items.iter().peekable();
-        loop {
-            let Some(label) = iter.next() else {
-                break;
-            };
+        while let Some(label) = iter.next() {
             let is_last = iter.peek().is_none();
             label.serialize(w, is_last);
         }
@@ -254,7 +371,6 @@ fn serialize_str<'a, const LK: usize>(v: &'a str, w: &mut Writer<'a, '_, LK>, is
     }
 }
 
-
 impl<const LLEN: usize> fmt::Display for Label<'_, LLEN> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if self.items.is_empty() {
@@ -303,7 +419,7 @@ impl<'a> Iterator for LabelRunIter<'a> {
         } else {
             let bytes = &self.data[1..1 + len];
             // invariant: the LabelRun is only constructed via parse(), which
-            // validates the utf8 as part of parsing.
+            // validates the utf8 as part of parsing, or over an OwnedLabel.
             let s = core::str::from_utf8(bytes).unwrap();
 
             self.data = &self.data[1 + len..];
@@ -318,6 +434,36 @@ impl<'a> Iterator for LabelRunIter<'a> {
     }
 }
 
+struct LabelRunSegments<'a> {
+    data: &'a [u8],
+    context: &'a [u8],
+}
+
+impl<'a> Iterator for LabelRunSegments<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.data.len() < 2 {
+                return None;
+            }
+
+            let len = self.data[0] as usize;
+            if len & 0xc0 > 0 {
+                let offset = ((len & 0x3f) << 8) | (self.data[1] as usize);
+                self.data = &self.context[offset..];
+                continue;
+            }
+
+            let bytes = &self.data[1..1 + len];
+            self.data = &self.data[1 + len..];
+            // invariant: the LabelRun is only constructed via parse(), which
+            // validates the utf8 as part of parsing, or over an OwnedLabel.
+            return Some(core::str::from_utf8(bytes).unwrap());
+        }
+    }
+}
+
 struct LabelStrIter<'a> {
     data: &'a str,
 }
@@ -363,19 +509,7 @@ impl Default for LabelPart<'_> {
 
 impl PartialEq for LabelPart<'_> {
     fn eq(&self, other: &Self) -> bool {
-        let mut self_iter = self.iter();
-        let mut other_iter = other.iter();
-        loop {
-            match (self_iter.next(), other_iter.next()) {
-                (Some(self_part), Some(other_part)) => {
-                    if self_part != other_part {
-                        return false;
-                    }
-                }
-                (None, None) => return true,
-                _ => return false,
-            }
-        }
+        cmp_segments(self.iter(), other.iter()).is_eq()
     }
 }
 
@@ -398,34 +532,82 @@ impl<'a> Iterator for LabelPartIter<'a> {
     }
 }
 
+enum LabelPartSegments<'a> {
+    Run(LabelRunSegments<'a>),
+    Str(LabelStrIter<'a>),
+}
+
+impl<'a> Iterator for LabelPartSegments<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            LabelPartSegments::Run(iter) => iter.next(),
+            LabelPartSegments::Str(iter) => iter.next(),
+        }
+    }
+}
+
+/// Compare segments the way DNS compares names, i.e. ignoring ASCII case (RFC 4343).
+fn cmp_segments<'x, 'y>(
+    mut a: impl Iterator<Item = &'x str>,
+    mut b: impl Iterator<Item = &'y str>,
+) -> Ordering {
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) => match lowercase(x).cmp(lowercase(y)) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            },
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+        }
+    }
+}
+
+fn lowercase(segment: &str) -> impl Iterator<Item = u8> + '_ {
+    segment.bytes().map(|b| b.to_ascii_lowercase())
+}
+
 impl<const LLEN: usize> PartialEq for Label<'_, LLEN> {
     fn eq(&self, other: &Self) -> bool {
-        self.iter().eq(other.iter())
+        cmp_segments(self.iter(), other.iter()).is_eq()
     }
 }
 
 impl<const LLEN: usize> PartialEq<&str> for Label<'_, LLEN> {
     fn eq(&self, other: &&str) -> bool {
-        let mut self_iter = self.iter();
-        let mut other_iter = other.split('.');
+        cmp_segments(self.iter(), other.split('.')).is_eq()
+    }
+}
 
-        loop {
-            let (s1, s2) = (self_iter.next(), other_iter.next());
-            match (s1, s2) {
-                (Some(self_part), Some(other_part)) => {
-                    if self_part != other_part {
-                        return false;
-                    }
-                }
-                (None, None) => return true,
-                _ => return false,
+impl<const LLEN: usize> Eq for Label<'_, LLEN> {}
+
+impl<const LLEN: usize> PartialOrd for Label<'_, LLEN> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const LLEN: usize> Ord for Label<'_, LLEN> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_segments(self.iter(), other.iter())
+    }
+}
+
+impl<const LLEN: usize> Hash for Label<'_, LLEN> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Same segments whichever way the label is split into parts and runs.
+        for segment in self.iter() {
+            state.write_usize(segment.len());
+            for b in lowercase(segment) {
+                state.write_u8(b);
             }
         }
     }
 }
 
-impl<const LLEN: usize> Eq for Label<'_, LLEN> {}
-
 impl<const LLEN: usize> fmt::Debug for Label<'_, LLEN> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         core::write!(f, "Label(\"")?;
@@ -456,8 +638,55 @@ impl<const LLEN: usize> defmt::Format for Label<'_, LLEN> {
     }
 }
 
+impl PartialEq for OwnedLabel {
+    fn eq(&self, other: &Self) -> bool {
+        cmp_segments(self.iter(), other.iter()).is_eq()
+    }
+}
+
+impl PartialEq<&str> for OwnedLabel {
+    fn eq(&self, other: &&str) -> bool {
+        cmp_segments(self.iter(), other.split('.')).is_eq()
+    }
+}
+
+impl<const LLEN: usize> PartialEq<Label<'_, LLEN>> for OwnedLabel {
+    fn eq(&self, other: &Label<'_, LLEN>) -> bool {
+        cmp_segments(self.iter(), other.iter()).is_eq()
+    }
+}
+
+impl<const LLEN: usize> PartialEq<OwnedLabel> for Label<'_, LLEN> {
+    fn eq(&self, other: &OwnedLabel) -> bool {
+        cmp_segments(self.iter(), other.iter()).is_eq()
+    }
+}
+
+impl Eq for OwnedLabel {}
+
+impl fmt::Display for OwnedLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.label::<1>(), f)
+    }
+}
+
+impl fmt::Debug for OwnedLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.label::<1>(), f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for OwnedLabel {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::Format::format(&self.label::<1>(), fmt)
+    }
+}
+
 #[cfg(all(feature = "std", test))]
 mod test {
+    use std::hash::DefaultHasher;
+
     use crate::test::init_test_log;
 
     use super::*;
@@ -534,6 +763,58 @@ mod test {
         assert_eq!(created_label, "example.com");
     }
 
+    #[test]
+    fn label_eq_ignores_ascii_case() {
+        let data = [
+            0x05, 0x4C, 0x4F, 0x43, 0x41, 0x4C, 0x00, // LOCAL
+            0x04, 0x5F, 0x55, 0x44, 0x50, 0xC0, 0x00, // _UDP + pointer to LOCAL
+            0x0B, 0x5F, 0x4D, 0x79, 0x2D, 0x53, 0x65, 0x72, 0x76, 0x69, 0x63, 0x65, 0xC0,
+            0x07, // _My-Service + pointer to _UDP
+        ];
+        let context = &data[..];
+
+        let (_, parsed_label) = Label::<4>::parse(&data[14..], context).unwrap();
+
+        assert_eq!(parsed_label, "_my-service._udp.local");
+        assert_eq!(parsed_label, "_MY-SERVICE._Udp.Local");
+        assert_ne!(parsed_label, "_my-service._tcp.local");
+
+        let mut created_label = Label::<4>::new("_my-service");
+        created_label.push_back("_udp.local");
+        assert_eq!(parsed_label, created_label);
+        assert_eq!(parsed_label.cmp(&created_label), Ordering::Equal);
+
+        // Different splits into parts, runs and case hash the same.
+        let hash = |label: &Label<4>| {
+            let mut hasher = DefaultHasher::new();
+            label.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&parsed_label), hash(&created_label));
+        assert_eq!(
+            hash(&parsed_label),
+            hash(&Label::new("_MY-SERVICE._UDP.LOCAL"))
+        );
+    }
+
+    #[test]
+    fn label_ord_ignores_ascii_case() {
+        let data = [
+            0x01, 0x42, 0x05, 0x6C, 0x6F, 0x63, 0x61, 0x6C, 0x00, // B.local
+            0x01, 0x61, 0xC0, 0x02, // a + pointer to local
+        ];
+        let context = &data[..];
+
+        let (_, upper_b) = Label::<4>::parse(&data[..], context).unwrap();
+        let (_, lower_a) = Label::<4>::parse(&data[9..], context).unwrap();
+
+        // Byte for byte "B" < "a", but not ignoring case.
+        assert!(lower_a < upper_b);
+        assert!(Label::<4>::new("b.local") > lower_a);
+        assert!(Label::<4>::new("a") < lower_a);
+        assert!(Label::<4>::new("A.LOCAL") <= lower_a);
+    }
+
     #[test]
     fn default_label_is_empty() {
         let label: Label<4> = Label::default();
@@ -546,6 +827,118 @@ mod test {
         assert!(!label.is_empty());
     }
 
+    #[test]
+    fn owned_label_from_segments() {
+        let label = Label::<4>::new("_test._tcp.local");
+        let owned =
+            OwnedLabel::from_segments(core::iter::once("martin_test (2)").chain(label.segments()))
+                .unwrap();
+        assert_eq!(owned, "martin_test (2)._test._tcp.local");
+
+        let mut buffer = [0u8; 256];
+        let mut buffer = Writer::<10>::new(&mut buffer);
+        owned.label::<1>().serialize(&mut buffer);
+        assert_eq!(
+            buffer.into_inner(),
+            b"\x0fmartin_test (2)\x05_test\x04_tcp\x05local\x00"
+        );
+    }
+
+    #[test]
+    fn owned_label_eq_parsed() {
+        let data = [
+            0x07, 0x65, 0x78, 0x61, 0x6D, 0x70, 0x6C, 0x65, 0x03, 0x63, 0x6F, 0x6D,
+            0x00, // example.com
+        ];
+
+        let (_, parsed_label) = Label::<4>::parse(&data, &data).unwrap();
+        let owned: OwnedLabel = parsed_label.try_to_owned().unwrap();
+
+        assert_eq!(owned, parsed_label);
+        assert_eq!(owned, "example.com");
+    }
+
+    #[test]
+    fn owned_label_outlives_packet() {
+        let owned: OwnedLabel = {
+            let data = [
+                0x05, 0x6C, 0x6F, 0x63, 0x61, 0x6C, 0x00, // local
+                0x04, 0x6D, 0x69, 0x6E, 0x69, 0xC0, 0x00, // mini + pointer to local
+            ];
+            let (_, parsed_label) = Label::<4>::parse(&data[7..], &data).unwrap();
+            parsed_label.try_to_owned().unwrap()
+        };
+
+        assert_eq!(owned, "mini.local");
+        assert_eq!(owned, Label::<4>::new("MINI.local"));
+        assert_eq!(format!("{}", owned), "mini.local");
+    }
+
+    #[test]
+    fn owned_label_keeps_dot_in_segment() {
+        let data = [
+            0x05, 0x61, 0x2E, 0x62, 0x20, 0x63, // a.b c
+            0x05, 0x6C, 0x6F, 0x63, 0x61, 0x6C, 0x00, // local
+        ];
+        let (_, parsed_label) = Label::<4>::parse(&data, &data).unwrap();
+        let owned = parsed_label.try_to_owned().unwrap();
+        assert_eq!(owned, parsed_label);
+
+        let mut buffer = [0u8; 256];
+        let mut writer = Writer::<10>::new(&mut buffer);
+        owned.label::<1>().serialize(&mut writer);
+        let written = writer.into_inner();
+        assert_eq!(written, &data[..]);
+
+        let (_, reparsed) = Label::<4>::parse(written, written).unwrap();
+        assert_eq!(reparsed, parsed_label);
+    }
+
+    #[test]
+    fn owned_label_rejects_long_segment() {
+        let long = "a".repeat(MAX_SEGMENT_LEN + 1);
+        let label = Label::<4>::new(&long);
+        assert!(label.try_to_owned().is_none());
+    }
+
+    #[test]
+    fn owned_label_rejects_long_name() {
+        let segment = "a".repeat(MAX_SEGMENT_LEN);
+        let long = [segment.as_str(); 4].join(".");
+        let label = Label::<4>::new(&long);
+        assert!(label.try_to_owned().is_none());
+
+        let fits = [segment.as_str(); 3].join(".");
+        assert!(Label::<4>::new(&fits).try_to_owned().is_some());
+    }
+
+    #[test]
+    fn pop_front_of_parsed_label() {
+        let data = [
+            0x05, 0x6C, 0x6F, 0x63, 0x61, 0x6C, 0x00, // local
+            0x04, 0x5F, 0x67, 0x70, 0x75, 0x04, 0x5F, 0x73, 0x75, 0x62, 0xC0,
+            0x00, // _gpu._sub + pointer to local
+        ];
+        let (_, mut label) = Label::<4>::parse(&data[7..], &data).unwrap();
+
+        label.pop_front();
+        assert_eq!(label, "_sub.local");
+        label.pop_front();
+        assert_eq!(label, "local");
+
+        let mut buffer = [0u8; 256];
+        let mut buffer = Writer::<10>::new(&mut buffer);
+        label.serialize(&mut buffer);
+        assert_eq!(buffer.into_inner(), b"\x05local\x00");
+
+        let mut label = Label::<4>::new("_gpu._sub");
+        label.push_back("local");
+        label.pop_front();
+        assert_eq!(label, "_sub.local");
+        label.pop_front();
+        assert_eq!(label, "local");
+    }
+
     #[test]
     fn serialize_str_label_truncated_no_panic() {
         // Buffer too small to fit the serialized label.
@@ -1,7 +1,4 @@
-use nom::error::make_error;
-use nom::number::complete::be_u16;
-use nom::IResult;
-
+pub use error::{ParseError, Section};
 pub use flags::*;
 pub use label::*;
 pub use query::*;
@@ -10,6 +7,7 @@ pub use reqres::*;
 
 use crate::writer::Writer;
 
+mod error;
 mod flags;
 mod label;
 mod query;
@@ -17,28 +15,40 @@ mod records;
 mod reqres;
 
 #[derive(Debug)]
-pub enum Message<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> {
-    Request(Request<'a, QLEN, LLEN>),
-    Response(Response<'a, QLEN, ALEN, LLEN>),
+pub enum Message<
+    'a,
+    const QLEN: usize,
+    const ALEN: usize,
+    const LLEN: usize,
+    const NSLEN: usize = ALEN,
+    const ARLEN: usize = ALEN,
+> {
+    Request(Request<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>),
+    Response(Response<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>),
 }
 
-impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> Message<'a, QLEN, ALEN, LLEN> {
-    pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+impl<
+        'a,
+        const QLEN: usize,
+        const ALEN: usize,
+        const LLEN: usize,
+        const NSLEN: usize,
+        const ARLEN: usize,
+    > Message<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>
+{
+    pub fn parse(input: &'a [u8]) -> Result<Self, ParseError> {
         trace!("Message::parse");
         if input.len() < 4 {
             warn!("Message::parse too small message: {}", input.len(),);
-            return Err(nom::Err::Failure(make_error(
-                input,
-                nom::error::ErrorKind::LengthValue,
-            )));
+            return Err(ParseError::Truncated {
+                offset: input.len(),
+            });
         }
-        let (_, flags) = be_u16(&input[2..])?;
+        let flags = u16::from_be_bytes([input[2], input[3]]);
         if flags & 0x8000 == 0 {
-            let (input, request) = Request::parse(input)?;
-            Ok((input, Message::Request(request)))
+            Ok(Message::Request(Request::parse(input)?))
         } else {
-            let (input, response) = Response::parse(input)?;
-            Ok((input, Message::Response(response)))
+            Ok(Message::Response(Response::parse(input)?))
         }
     }
 
@@ -53,8 +63,14 @@ impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> Message<'a, QL
 }
 
 #[cfg(feature = "defmt")]
-impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> defmt::Format
-    for Message<'a, QLEN, ALEN, LLEN>
+impl<
+        'a,
+        const QLEN: usize,
+        const ALEN: usize,
+        const LLEN: usize,
+        const NSLEN: usize,
+        const ARLEN: usize,
+    > defmt::Format for Message<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>
 {
     fn format(&self, fmt: defmt::Formatter) {
         match self {
@@ -121,4 +137,31 @@ mod test {
 
         Message::<12, 12, 4>::parse(FAIL).unwrap_err();
     }
+
+    #[test]
+    fn parse_errors() {
+        init_test_log();
+
+        let err = Message::<4, 4, 4>::parse(&[0, 0]).unwrap_err();
+        assert_eq!(err, ParseError::Truncated { offset: 2 });
+
+        // Header says one query, but there is nothing after it.
+        const NO_QUERY: &[u8] = &[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        let err = Message::<4, 4, 4>::parse(NO_QUERY).unwrap_err();
+        assert_eq!(err, ParseError::Truncated { offset: 12 });
+
+        // Pointer past the end of the message.
+        const BAD_POINTER: &[u8] = &[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xc0, 0x40, 0, 1, 0, 1];
+        let err = Message::<4, 4, 4>::parse(BAD_POINTER).unwrap_err();
+        assert_eq!(err, ParseError::BadPointer { offset: 12 });
+
+        // Pointer to itself.
+        const LOOP: &[u8] = &[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0xc0, 12, 0, 1, 0, 1];
+        let err = Message::<4, 4, 4>::parse(LOOP).unwrap_err();
+        assert_eq!(err, ParseError::PointerLoop { offset: 12 });
+
+        const NOT_UTF8: &[u8] = &[0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0xff, 0, 0, 1, 0, 1];
+        let err = Message::<4, 4, 4>::parse(NOT_UTF8).unwrap_err();
+        assert_eq!(err, ParseError::NotUtf8 { offset: 12 });
+    }
 }
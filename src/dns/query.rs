@@ -1,15 +1,21 @@
+use core::cmp::Ordering;
+
 use nom::number::complete::be_u32;
-use nom::{number::complete::be_u16, IResult};
+use nom::{bytes::complete::take, number::complete::be_u16};
+
+use super::error::IResult;
 
 use super::records::Record;
 use super::Label;
 use crate::writer::Writer;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Query<'a, const LLEN: usize> {
     pub name: Label<'a, LLEN>,
     pub qtype: QType,
     pub qclass: QClass,
+    /// The QU bit, asking for a unicast response (RFC 6762 5.4).
+    pub unicast_response: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -24,28 +30,36 @@ pub enum QType {
     Unknown(u16),
 }
 
+/// The class of a query or record.
+///
+/// The top bit of the class is not part of it. That bit is
+/// [`Query::unicast_response`] and [`Answer::cache_flush`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u16)]
 pub enum QClass {
     IN = 1,
-    Multicast = 32769, // (IN + Cache flush bit)
     Unknown(u16),
 }
 
+/// The top bit of the class, with a different meaning in queries and records.
+const CLASS_FLAG: u16 = 0x8000;
+
 impl<'a, const LLEN: usize> Query<'a, LLEN> {
-    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<&'a [u8], Self> {
+    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<'a, Self> {
         trace!("Query::parse");
         let (input, name) = Label::parse(input, context)?;
         let (input, qtype) = be_u16(input)?;
         let qtype = QType::from_u16(qtype);
         let (input, qclass) = be_u16(input)?;
-        let qclass = QClass::from_u16(qclass);
+        let unicast_response = qclass & CLASS_FLAG > 0;
+        let qclass = QClass::from_u16(qclass & !CLASS_FLAG);
         Ok((
             input,
             Query {
                 name,
                 qtype,
                 qclass,
+                unicast_response,
             },
         ))
     }
@@ -53,7 +67,8 @@ impl<'a, const LLEN: usize> Query<'a, LLEN> {
     pub(crate) fn serialize<'b, const LK: usize>(&self, w: &mut Writer<'a, 'b, LK>) {
         self.name.serialize(w);
         w.write(&self.qtype.to_u16().to_be_bytes());
-        w.write(&self.qclass.to_u16().to_be_bytes());
+        let flag = if self.unicast_response { CLASS_FLAG } else { 0 };
+        w.write(&(self.qclass.to_u16() | flag).to_be_bytes());
     }
 }
 
@@ -62,6 +77,9 @@ pub struct Answer<'a, const LLEN: usize> {
     pub name: Label<'a, LLEN>,
     pub atype: QType,
     pub aclass: QClass,
+    /// The cache-flush bit, telling that this record replaces any other of the same
+    /// name and type (RFC 6762 10.2).
+    pub cache_flush: bool,
     pub ttl: u32,
     pub record: Record<'a, LLEN>,
 }
@@ -96,7 +114,6 @@ impl QClass {
     pub fn from_u16(value: u16) -> Self {
         match value {
             1 => QClass::IN,
-            32769 => QClass::Multicast,
             _ => QClass::Unknown(value),
         }
     }
@@ -104,38 +121,62 @@ impl QClass {
     pub fn to_u16(&self) -> u16 {
         match self {
             QClass::IN => 1,
-            QClass::Multicast => 32769,
             QClass::Unknown(value) => *value,
         }
     }
 }
 
 impl<'a, const LLEN: usize> Answer<'a, LLEN> {
-    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<&'a [u8], Self> {
+    /// Parse a resource record.
+    ///
+    /// Records of types we don't handle (such as NSEC) are skipped and give `None`.
+    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<'a, Option<Self>> {
         let (input, name) = Label::parse(input, context)?;
         let (input, atype) = be_u16(input)?;
         let atype = QType::from_u16(atype);
         let (input, aclass) = be_u16(input)?;
-        let aclass = QClass::from_u16(aclass);
+        let cache_flush = aclass & CLASS_FLAG > 0;
+        let aclass = QClass::from_u16(aclass & !CLASS_FLAG);
 
         let (input, ttl) = be_u32(input)?;
+
+        if matches!(atype, QType::Any | QType::Unknown(_)) {
+            trace!("Skip record of type: {:?}", atype);
+            let (input, rdlen) = be_u16(input)?;
+            let (input, _) = take(rdlen)(input)?;
+            return Ok((input, None));
+        }
+
         let (input, record) = Record::parse(input, context, atype)?;
         Ok((
             input,
-            Answer {
+            Some(Answer {
                 name,
                 atype,
                 aclass,
+                cache_flush,
                 ttl,
                 record,
-            },
+            }),
         ))
     }
 
+    /// Lexicographic ordering used for simultaneous probe tie-breaking (RFC 6762 8.2).
+    ///
+    /// Compares class (without cache flush bit), then type, then rdata.
+    pub(crate) fn cmp_probe(&self, other: &Answer<'_, LLEN>) -> Ordering {
+        self.aclass
+            .to_u16()
+            .cmp(&other.aclass.to_u16())
+            .then_with(|| self.atype.to_u16().cmp(&other.atype.to_u16()))
+            .then_with(|| self.record.cmp_rdata(&other.record))
+    }
+
     pub(crate) fn serialize<'b, const LK: usize>(&self, w: &mut Writer<'a, 'b, LK>) {
         self.name.serialize(w);
         w.write(&self.atype.to_u16().to_be_bytes());
-        w.write(&self.aclass.to_u16().to_be_bytes());
+        let flag = if self.cache_flush { CLASS_FLAG } else { 0 };
+        w.write(&(self.aclass.to_u16() | flag).to_be_bytes());
         w.write(&self.ttl.to_be_bytes());
         self.record.serialize(w);
     }
@@ -146,10 +187,11 @@ impl<'a, const LLEN: usize> defmt::Format for Query<'a, LLEN> {
     fn format(&self, fmt: defmt::Formatter) {
         defmt::write!(
             fmt,
-            "Query {{ name: {:?}, qtype: {:?}, qclass: {:?} }}",
+            "Query {{ name: {:?}, qtype: {:?}, qclass: {:?}, unicast_response: {} }}",
             self.name,
             self.qtype,
-            self.qclass
+            self.qclass,
+            self.unicast_response
         );
     }
 }
@@ -175,7 +217,6 @@ impl defmt::Format for QClass {
     fn format(&self, fmt: defmt::Formatter) {
         let qclass_str = match self {
             QClass::IN => "IN",
-            QClass::Multicast => "Multicast",
             QClass::Unknown(_) => "Unknown",
         };
         defmt::write!(fmt, "QClass({=str})", qclass_str);
@@ -187,10 +228,11 @@ impl<'a, const LLEN: usize> defmt::Format for Answer<'a, LLEN> {
     fn format(&self, fmt: defmt::Formatter) {
         defmt::write!(
             fmt,
-            "Answer {{ name: {:?}, atype: {:?}, aclass: {:?}, ttl: {}, record: {:?} }}",
+            "Answer {{ name: {:?}, atype: {:?}, aclass: {:?}, cache_flush: {}, ttl: {}, record: {:?} }}",
             self.name,
             self.atype,
             self.aclass,
+            self.cache_flush,
             self.ttl,
             self.record
         );
@@ -211,6 +253,7 @@ mod tests {
             name,
             qtype: QType::A,
             qclass: QClass::IN,
+            unicast_response: true,
         };
 
         let mut buffer = [0u8; 256];
@@ -229,6 +272,7 @@ mod tests {
             name,
             atype: QType::A,
             aclass: QClass::IN,
+            cache_flush: true,
             ttl: 120,
             record: Record::A(A {
                 address: Ipv4Addr::new(192, 168, 1, 1),
@@ -240,6 +284,6 @@ mod tests {
         answer.serialize(&mut buffer);
         let (_, parsed_answer) = Answer::parse(buffer.into_inner(), &[1]).unwrap();
 
-        assert_eq!(answer, parsed_answer);
+        assert_eq!(Some(answer), parsed_answer);
     }
 }
@@ -1,7 +1,9 @@
+use core::cmp::Ordering;
 use core::net::{Ipv4Addr, Ipv6Addr};
 use core::str;
-use nom::error::make_error;
-use nom::{bytes::complete::take, number::complete::be_u16, IResult};
+use nom::{bytes::complete::take, number::complete::be_u16};
+
+use super::error::{Error, ErrorKind, IResult};
 
 use super::query::QType;
 use super::Label;
@@ -22,7 +24,7 @@ impl<'a, const LLEN: usize> Record<'a, LLEN> {
         input: &'a [u8],
         context: &'a [u8],
         record_type: QType,
-    ) -> IResult<&'a [u8], Self> {
+    ) -> IResult<'a, Self> {
         trace!("Record::parse");
         match record_type {
             QType::A => {
@@ -45,20 +47,35 @@ impl<'a, const LLEN: usize> Record<'a, LLEN> {
                 let (input, record) = SRV::parse(input, context)?;
                 Ok((input, Record::SRV(record)))
             }
-            QType::Any => {
-                warn!("Record::parse with ANY value");
-                Err(nom::Err::Error(make_error(
+            QType::Any | QType::Unknown(_) => {
+                warn!("Record::parse unsupported type: {:?}", record_type);
+                Err(Error::failure(
                     input,
-                    nom::error::ErrorKind::Tag,
-                )))
+                    ErrorKind::Unsupported(record_type.to_u16()),
+                ))
             }
-            QType::Unknown(_) => Err(nom::Err::Error(make_error(
-                input,
-                nom::error::ErrorKind::Tag,
-            ))),
         }
     }
 
+    /// Compare the uncompressed rdata of two records byte by byte.
+    pub(crate) fn cmp_rdata(&self, other: &Record<'_, LLEN>) -> Ordering {
+        // Enough for the largest record we produce: SRV with a max length name.
+        let mut buf1 = [0_u8; 300];
+        let mut buf2 = [0_u8; 300];
+
+        // No label compression, since the writers have no lookup entries.
+        let mut w1 = Writer::<0>::new(&mut buf1);
+        self.serialize(&mut w1);
+        let mut w2 = Writer::<0>::new(&mut buf2);
+        other.serialize(&mut w2);
+
+        // Skip the rdata length.
+        let d1 = w1.into_inner().get(2..).unwrap_or_default();
+        let d2 = w2.into_inner().get(2..).unwrap_or_default();
+
+        d1.cmp(d2)
+    }
+
     pub(crate) fn serialize<'b, const LK: usize>(&self, w: &mut Writer<'a, 'b, LK>) {
         match self {
             Record::A(record) => record.serialize(w),
@@ -77,13 +94,14 @@ pub struct A {
 }
 
 impl A {
-    pub(crate) fn parse(input: &[u8]) -> IResult<&[u8], A> {
+    pub(crate) fn parse(input: &[u8]) -> IResult<'_, A> {
         trace!("A::parse");
+        let start = input;
         let (input, len) = be_u16(input)?;
         let (input, address) = take(len)(input)?;
         let address = Ipv4Addr::from(
             <[u8; 4]>::try_from(address)
-                .map_err(|_| nom::Err::Failure(make_error(input, nom::error::ErrorKind::Fail)))?,
+                .map_err(|_| Error::failure(start, ErrorKind::BadRecord(QType::A.to_u16())))?,
         );
         Ok((input, A { address }))
     }
@@ -101,13 +119,15 @@ pub struct AAAA {
 }
 
 impl AAAA {
-    pub(crate) fn parse(input: &[u8]) -> IResult<&[u8], AAAA> {
+    pub(crate) fn parse(input: &[u8]) -> IResult<'_, AAAA> {
         trace!("AAAA::parse");
+        let start = input;
         let (input, len) = be_u16(input)?;
         let (input, address) = take(len)(input)?;
-        let address = Ipv6Addr::from(<[u8; 16]>::try_from(address).map_err(|_| {
-            nom::Err::Failure(make_error(input, nom::error::ErrorKind::LengthValue))
-        })?);
+        let address = Ipv6Addr::from(
+            <[u8; 16]>::try_from(address)
+                .map_err(|_| Error::failure(start, ErrorKind::BadRecord(QType::AAAA.to_u16())))?,
+        );
         Ok((input, AAAA { address }))
     }
 
@@ -124,7 +144,7 @@ pub struct PTR<'a, const LLEN: usize> {
 }
 
 impl<'a, const LLEN: usize> PTR<'a, LLEN> {
-    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<&'a [u8], Self> {
+    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<'a, Self> {
         trace!("PTR::parse");
         let (input, _) = be_u16(input)?;
         let (input, name) = Label::parse(input, context)?;
@@ -142,23 +162,71 @@ impl<'a, const LLEN: usize> PTR<'a, LLEN> {
 // Struct for TXT record
 #[derive(Debug, PartialEq, Eq)]
 pub struct TXT<'a> {
-    pub text: &'a str,
+    /// The raw rdata, a sequence of length prefixed strings (RFC 6763 6).
+    pub data: &'a [u8],
 }
 
 impl<'a> TXT<'a> {
-    pub(crate) fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+    pub(crate) fn parse(input: &'a [u8]) -> IResult<'a, Self> {
         trace!("TXT::parse");
-        let (input, text_len) = be_u16(input)?;
-        let (input, text) = take(text_len)(input)?;
-        let text = str::from_utf8(text).map_err(|_| {
-            nom::Err::Failure(make_error(input, nom::error::ErrorKind::AlphaNumeric))
-        })?;
-        Ok((input, TXT { text }))
+        let (input, data_len) = be_u16(input)?;
+        let (input, data) = take(data_len)(input)?;
+        Ok((input, TXT { data }))
     }
 
     pub(crate) fn serialize<'b, const LK: usize>(&self, w: &mut Writer<'a, 'b, LK>) {
-        w.write(&(self.text.len() as u16).to_be_bytes());
-        w.write(self.text.as_bytes());
+        w.write(&(self.data.len() as u16).to_be_bytes());
+        w.write(self.data);
+    }
+
+    /// Iterate the `key=value` entries of the record.
+    pub fn iter(&self) -> TxtIter<'a> {
+        TxtIter::new(self.data)
+    }
+}
+
+/// Iterator over the entries of a TXT record.
+///
+/// Each entry is a key, with an optional value. `key` without `=` has no value,
+/// while `key=` has an empty value. Empty strings and entries with keys that
+/// are not UTF-8 are skipped.
+pub struct TxtIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TxtIter<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        TxtIter { data }
+    }
+}
+
+impl<'a> Iterator for TxtIter<'a> {
+    type Item = (&'a str, Option<&'a [u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&len, rest) = self.data.split_first()?;
+            let len = len as usize;
+
+            if len > rest.len() {
+                trace!("TXT string longer than record");
+                self.data = &[];
+                return None;
+            }
+
+            let (entry, rest) = rest.split_at(len);
+            self.data = rest;
+
+            let (key, value) = match entry.iter().position(|b| *b == b'=') {
+                Some(pos) => (&entry[..pos], Some(&entry[pos + 1..])),
+                None => (entry, None),
+            };
+
+            match str::from_utf8(key) {
+                Ok(key) if !key.is_empty() => return Some((key, value)),
+                _ => continue,
+            }
+        }
     }
 }
 
@@ -172,7 +240,7 @@ pub struct SRV<'a, const LLEN: usize> {
 }
 
 impl<'a, const LLEN: usize> SRV<'a, LLEN> {
-    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<&'a [u8], Self> {
+    pub(crate) fn parse(input: &'a [u8], context: &'a [u8]) -> IResult<'a, Self> {
         trace!("SRV::parse");
         let (input, _) = be_u16(input)?;
         let (input, priority) = be_u16(input)?;
@@ -244,7 +312,7 @@ impl<'a, const LLEN: usize> defmt::Format for PTR<'a, LLEN> {
 #[cfg(feature = "defmt")]
 impl<'a> defmt::Format for TXT<'a> {
     fn format(&self, fmt: defmt::Formatter) {
-        defmt::write!(fmt, "TXT {{ text: {:?} }}", self.text);
+        defmt::write!(fmt, "TXT {{ data: {=[u8]} }}", self.data);
     }
 }
 
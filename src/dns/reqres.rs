@@ -1,29 +1,58 @@
-use nom::{error::make_error, number::complete::be_u16, IResult};
+use nom::number::complete::be_u16;
+
+use super::error::{Error, ErrorKind, IResult, ParseError, Section};
 
 use super::flags::Flags;
 use super::query::{Answer, Query};
 use crate::vec::Vec;
 use crate::writer::Writer;
 
-const ZERO_U16: [u8; 2] = 0u16.to_be_bytes();
-
+/// A DNS request (query).
+///
+/// * `NSLEN` - Capacity for the authority section. Defaults to `ALEN`.
+/// * `ARLEN` - Capacity for the additional section. Defaults to `ALEN`.
 #[derive(Debug, PartialEq, Eq)]
-pub struct Request<'a, const QLEN: usize, const LLEN: usize> {
+pub struct Request<
+    'a,
+    const QLEN: usize,
+    const ALEN: usize,
+    const LLEN: usize,
+    const NSLEN: usize = ALEN,
+    const ARLEN: usize = ALEN,
+> {
     pub id: u16,
     pub flags: Flags,
     pub(crate) queries: Vec<Query<'a, LLEN>, QLEN>,
+    /// Known answers the querier already holds (RFC 6762 7.1).
+    pub(crate) answers: Vec<Answer<'a, LLEN>, ALEN>,
+    /// Proposed records when the request is a probe.
+    pub(crate) authorities: Vec<Answer<'a, LLEN>, NSLEN>,
+    pub(crate) additionals: Vec<Answer<'a, LLEN>, ARLEN>,
 }
 
-impl<'a, const QLEN: usize, const LLEN: usize> Request<'a, QLEN, LLEN> {
-    pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+impl<
+        'a,
+        const QLEN: usize,
+        const ALEN: usize,
+        const LLEN: usize,
+        const NSLEN: usize,
+        const ARLEN: usize,
+    > Request<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>
+{
+    pub fn parse(input: &'a [u8]) -> Result<Self, ParseError> {
         trace!("Request::parse");
+        let (_, v) = Self::do_parse(input).map_err(|e| Error::into_parse_error(e, input))?;
+        Ok(v)
+    }
+
+    fn do_parse(input: &'a [u8]) -> IResult<'a, Self> {
         let context = input;
         let (input, id) = be_u16(input)?;
         let (input, flags) = Flags::parse(input)?;
         let (input, qdcount) = be_u16(input)?;
-        let (input, _ancount) = be_u16(input)?;
-        let (input, _nscount) = be_u16(input)?;
-        let (input, _arcount) = be_u16(input)?;
+        let (input, ancount) = be_u16(input)?;
+        let (input, nscount) = be_u16(input)?;
+        let (input, arcount) = be_u16(input)?;
         let mut queries = Vec::new();
         let mut input = input;
         for _ in 0..qdcount {
@@ -31,43 +60,108 @@ impl<'a, const QLEN: usize, const LLEN: usize> Request<'a, QLEN, LLEN> {
             input = new_input;
             queries.push(query).map_err(|_| {
                 debug!("Request::parse too many queries: {}", qdcount);
-                nom::Err::Failure(make_error(input, nom::error::ErrorKind::TooLarge))
+                Error::failure(input, ErrorKind::Capacity(Section::Queries))
             })?;
         }
-        Ok((input, Request { id, flags, queries }))
+
+        // Known answers that don't fit are dropped. At worst that means
+        // answering with something the querier already has.
+        let mut answers = Vec::new();
+        let (input, _) = parse_section(input, context, ancount, &mut answers, None)?;
+
+        // The authority section is only used by probes, and all of it is
+        // needed for the tie-break.
+        let mut authorities = Vec::new();
+        let (input, _) = parse_section(
+            input,
+            context,
+            nscount,
+            &mut authorities,
+            Some(Section::Authorities),
+        )?;
+
+        let mut additionals = Vec::new();
+        let (input, _) = parse_section(input, context, arcount, &mut additionals, None)?;
+
+        Ok((
+            input,
+            Request {
+                id,
+                flags,
+                queries,
+                answers,
+                authorities,
+                additionals,
+            },
+        ))
     }
 
     pub fn serialize<'b, const LK: usize>(&self, w: &mut Writer<'a, 'b, LK>) {
         w.write(&self.id.to_be_bytes());
         self.flags.serialize(w);
         w.write(&(self.queries.len() as u16).to_be_bytes());
-        w.write(&ZERO_U16); // ANCOUNT
-        w.write(&ZERO_U16); // NSCOUNT
-        w.write(&ZERO_U16); // ARCOUNT
+        w.write(&(self.answers.len() as u16).to_be_bytes());
+        w.write(&(self.authorities.len() as u16).to_be_bytes());
+        w.write(&(self.additionals.len() as u16).to_be_bytes());
         for query in self.queries.iter() {
             query.serialize(w);
         }
+        for answer in self.answers.iter() {
+            answer.serialize(w);
+        }
+        for authority in self.authorities.iter() {
+            authority.serialize(w);
+        }
+        for additional in self.additionals.iter() {
+            additional.serialize(w);
+        }
     }
 }
 
+/// A DNS response.
+///
+/// * `NSLEN` - Capacity for the authority section. Defaults to `ALEN`.
+/// * `ARLEN` - Capacity for the additional section. Defaults to `ALEN`.
 #[derive(Debug, PartialEq, Eq)]
-pub struct Response<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> {
+pub struct Response<
+    'a,
+    const QLEN: usize,
+    const ALEN: usize,
+    const LLEN: usize,
+    const NSLEN: usize = ALEN,
+    const ARLEN: usize = ALEN,
+> {
     pub id: u16,
     pub flags: Flags,
     pub queries: Vec<Query<'a, LLEN>, QLEN>,
     pub answers: Vec<Answer<'a, LLEN>, ALEN>,
+    pub authorities: Vec<Answer<'a, LLEN>, NSLEN>,
+    pub additionals: Vec<Answer<'a, LLEN>, ARLEN>,
 }
 
-impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> Response<'a, QLEN, ALEN, LLEN> {
-    pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+impl<
+        'a,
+        const QLEN: usize,
+        const ALEN: usize,
+        const LLEN: usize,
+        const NSLEN: usize,
+        const ARLEN: usize,
+    > Response<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>
+{
+    pub fn parse(input: &'a [u8]) -> Result<Self, ParseError> {
         trace!("Response::parse");
+        let (_, v) = Self::do_parse(input).map_err(|e| Error::into_parse_error(e, input))?;
+        Ok(v)
+    }
+
+    fn do_parse(input: &'a [u8]) -> IResult<'a, Self> {
         let context = input;
         let (input, id) = be_u16(input)?;
         let (input, flags) = Flags::parse(input)?;
         let (input, qdcount) = be_u16(input)?;
         let (input, ancount) = be_u16(input)?;
-        let (input, _nscount) = be_u16(input)?;
-        let (input, _arcount) = be_u16(input)?;
+        let (input, nscount) = be_u16(input)?;
+        let (input, arcount) = be_u16(input)?;
 
         let mut queries = Vec::new();
         let mut input = input;
@@ -76,19 +170,27 @@ impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> Response<'a, Q
             input = new_input;
             queries.push(query).map_err(|_| {
                 debug!("Response::parse too many queries: {}", qdcount);
-                nom::Err::Failure(make_error(input, nom::error::ErrorKind::TooLarge))
+                Error::failure(input, ErrorKind::Capacity(Section::Queries))
             })?;
         }
 
         let mut answers = Vec::new();
-        for _ in 0..ancount {
-            let (new_input, answer) = Answer::parse(input, context)?;
-            input = new_input;
-            answers.push(answer).map_err(|_| {
-                debug!("Response::parse too many answers: {}", ancount);
-                nom::Err::Failure(make_error(input, nom::error::ErrorKind::TooLarge))
-            })?;
-        }
+        let (input, _) = parse_section(
+            input,
+            context,
+            ancount,
+            &mut answers,
+            Some(Section::Answers),
+        )?;
+
+        // Records in the authority and additional sections are helpful, but
+        // not required. Those that don't fit are dropped.
+        let mut authorities = Vec::new();
+        let (input, _) = parse_section(input, context, nscount, &mut authorities, None)?;
+
+        let mut additionals = Vec::new();
+        let (input, _) = parse_section(input, context, arcount, &mut additionals, None)?;
+
         Ok((
             input,
             Response {
@@ -96,6 +198,8 @@ impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> Response<'a, Q
                 flags,
                 queries,
                 answers,
+                authorities,
+                additionals,
             },
         ))
     }
@@ -105,42 +209,105 @@ impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> Response<'a, Q
         self.flags.serialize(w);
         w.write(&(self.queries.len() as u16).to_be_bytes());
         w.write(&(self.answers.len() as u16).to_be_bytes());
-        w.write(&ZERO_U16); // NSCOUNT
-        w.write(&ZERO_U16); // ARCOUNT
+        w.write(&(self.authorities.len() as u16).to_be_bytes());
+        w.write(&(self.additionals.len() as u16).to_be_bytes());
         for query in self.queries.iter() {
             query.serialize(w);
         }
         for answer in self.answers.iter() {
             answer.serialize(w);
         }
+        for authority in self.authorities.iter() {
+            authority.serialize(w);
+        }
+        for additional in self.additionals.iter() {
+            additional.serialize(w);
+        }
+    }
+
+    /// All records in the answer, authority and additional sections.
+    pub fn records(&self) -> impl Iterator<Item = &Answer<'a, LLEN>> + Clone {
+        self.answers
+            .iter()
+            .chain(self.authorities.iter())
+            .chain(self.additionals.iter())
     }
 }
 
+/// Parse `count` records into `section`, skipping record types we don't handle.
+///
+/// Records that don't fit in `section` fail the parse if `must_fit` names the
+/// section, and are dropped otherwise.
+fn parse_section<'a, const N: usize, const LLEN: usize>(
+    mut input: &'a [u8],
+    context: &'a [u8],
+    count: u16,
+    section: &mut Vec<Answer<'a, LLEN>, N>,
+    must_fit: Option<Section>,
+) -> IResult<'a, ()> {
+    for _ in 0..count {
+        let (new_input, answer) = Answer::parse(input, context)?;
+        input = new_input;
+
+        let Some(answer) = answer else {
+            continue;
+        };
+
+        if section.push(answer).is_err() {
+            debug!("Too many records in section: {}", count);
+            if let Some(section) = must_fit {
+                return Err(Error::failure(input, ErrorKind::Capacity(section)));
+            }
+        }
+    }
+
+    Ok((input, ()))
+}
+
 #[cfg(feature = "defmt")]
-impl<'a, const QLEN: usize, const LLEN: usize> defmt::Format for Request<'a, QLEN, LLEN> {
+impl<
+        'a,
+        const QLEN: usize,
+        const ALEN: usize,
+        const LLEN: usize,
+        const NSLEN: usize,
+        const ARLEN: usize,
+    > defmt::Format for Request<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>
+{
     fn format(&self, fmt: defmt::Formatter) {
         defmt::write!(
             fmt,
-            "Request {{ id: {}, flags: {:?}, queries: {:?} }}",
+            "Request {{ id: {}, flags: {:?}, queries: {:?}, answers: {:?}, authorities: {:?}, additionals: {:?} }}",
             self.id,
             self.flags,
-            self.queries
+            self.queries,
+            self.answers,
+            self.authorities,
+            self.additionals
         );
     }
 }
 
 #[cfg(feature = "defmt")]
-impl<'a, const QLEN: usize, const ALEN: usize, const LLEN: usize> defmt::Format
-    for Response<'a, QLEN, ALEN, LLEN>
+impl<
+        'a,
+        const QLEN: usize,
+        const ALEN: usize,
+        const LLEN: usize,
+        const NSLEN: usize,
+        const ARLEN: usize,
+    > defmt::Format for Response<'a, QLEN, ALEN, LLEN, NSLEN, ARLEN>
 {
     fn format(&self, fmt: defmt::Formatter) {
         defmt::write!(
             fmt,
-            "Response {{ id: {}, flags: {:?}, queries: {:?}, answers: {:?} }}",
+            "Response {{ id: {}, flags: {:?}, queries: {:?}, answers: {:?}, authorities: {:?}, additionals: {:?} }}",
             self.id,
             self.flags,
             self.queries,
-            self.answers
+            self.answers,
+            self.authorities,
+            self.additionals
         );
     }
 }
@@ -163,7 +330,7 @@ mod tests {
             0x00, 0x01, 0x00, 0x01,
         ];
 
-        let (_, request) = Request::<12, 4>::parse(&data).unwrap();
+        let request = Request::<12, 12, 4>::parse(&data).unwrap();
 
         assert_eq!(request.id, 0xAAAA);
         assert_eq!(request.flags.0, 0x0100);
@@ -198,7 +365,7 @@ mod tests {
             192, 168, 1, 3,
         ];
 
-        let (_, response) = Response::<12, 12, 4>::parse(&data).unwrap();
+        let response = Response::<12, 12, 4>::parse(&data).unwrap();
 
         assert_eq!(response.id, 0xAAAA);
         assert_eq!(response.flags.0, 0x8180);
@@ -249,7 +416,7 @@ mod tests {
             0x64,
         ];
 
-        let (_, response) = Response::<12, 12, 4>::parse(&data).unwrap();
+        let response = Response::<12, 12, 4>::parse(&data).unwrap();
 
         assert_eq!(response.id, 0xAAAA);
         assert_eq!(response.flags.0, 0x8180);
@@ -272,7 +439,7 @@ mod tests {
         assert_eq!(response.answers[1].aclass, QClass::IN);
         assert_eq!(response.answers[1].ttl, 60);
         if let Record::TXT(txt) = &response.answers[1].record {
-            assert_eq!(txt.text, "test txt record");
+            assert_eq!(txt.data, b"test txt record");
         } else {
             panic!("Expected TXT record");
         }
@@ -310,7 +477,7 @@ mod tests {
             0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, //
         ];
 
-        let (_, response) = Response::<12, 12, 4>::parse(&data).unwrap();
+        let response = Response::<12, 12, 4>::parse(&data).unwrap();
 
         assert_eq!(response.id, 0xAAAA);
         assert_eq!(response.flags.0, 0x8180);
@@ -377,7 +544,7 @@ mod tests {
             4, 10, 1, 1, 9,
         ];
 
-        let (_, response) = Response::<12, 12, 4>::parse(&data).unwrap();
+        let response = Response::<12, 12, 4>::parse(&data).unwrap();
 
         println!("{:#?}", response);
 
@@ -395,8 +562,47 @@ mod tests {
         let buffer = buffer.into_inner();
         println!("{:?}", buffer);
 
-        let (_, response2) = Response::<12, 12, 4>::parse(buffer).unwrap();
+        let response2 = Response::<12, 12, 4>::parse(buffer).unwrap();
+
+        assert_eq!(response, response2);
+    }
+
+    #[test]
+    fn parse_additionals_skip_unknown() {
+        init_test_log();
 
+        let data = [
+            0, 0, // Transaction ID
+            132, 0, // Response, Authoritative Answer
+            0, 0, // 0 questions
+            0, 1, // 1 answer
+            0, 0, // 0 authority RRs
+            0, 2, // 2 additional RRs
+            // a.local
+            1, 97, 5, 108, 111, 99, 97, 108, 0, //
+            0, 1, 0, 1, 0, 0, 0, 120, 0, 4, 10, 0, 0, 1, // A 10.0.0.1
+            // NSEC, which we don't handle
+            192, 12, 0, 47, 128, 1, 0, 0, 0, 120, 0, 3, 192, 12, 0, //
+            // A 10.0.0.2
+            192, 12, 0, 1, 128, 1, 0, 0, 0, 120, 0, 4, 10, 0, 0, 2,
+        ];
+
+        let response = Response::<1, 1, 4>::parse(&data).unwrap();
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(response.additionals.len(), 1);
+        let Record::A(a) = &response.additionals[0].record else {
+            panic!("Expected A record");
+        };
+        assert_eq!(a.address, Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(response.records().count(), 2);
+
+        // Round trip with all sections.
+        let mut buffer = [0u8; 256];
+        let mut w = Writer::<10>::new(&mut buffer);
+        let response = Response::<1, 1, 4>::parse(&data).unwrap();
+        response.serialize(&mut w);
+        let response2 = Response::<1, 1, 4>::parse(w.into_inner()).unwrap();
         assert_eq!(response, response2);
     }
 
@@ -409,12 +615,15 @@ mod tests {
             flags: Flags::standard_response(),
             queries: Vec::new(),
             answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
         };
 
         let query = Query {
             name: Label::new("_test._udp.local"),
             qtype: QType::PTR,
             qclass: QClass::IN,
+            unicast_response: false,
         };
         response.queries.push(query).unwrap();
 
@@ -422,6 +631,7 @@ mod tests {
             name: Label::new("_test._udp.local"),
             atype: QType::PTR,
             aclass: QClass::IN,
+            cache_flush: false,
             ttl: 4500,
             record: Record::PTR(PTR {
                 name: Label::new("test-service._test._udp.local"),
@@ -433,6 +643,7 @@ mod tests {
             name: Label::new("test-service._test._udp.local"),
             atype: QType::SRV,
             aclass: QClass::IN,
+            cache_flush: true,
             ttl: 120,
             record: Record::SRV(SRV {
                 priority: 0,
@@ -447,8 +658,11 @@ mod tests {
             name: Label::new("test-service._test._udp.local"),
             atype: QType::TXT,
             aclass: QClass::IN,
+            cache_flush: true,
             ttl: 120,
-            record: Record::TXT(TXT { text: "path=/test" }),
+            record: Record::TXT(TXT {
+                data: b"\x0apath=/test",
+            }),
         };
         response.answers.push(txt_answer).unwrap();
 
@@ -456,6 +670,7 @@ mod tests {
             name: Label::new("host.local"),
             atype: QType::A,
             aclass: QClass::IN,
+            cache_flush: true,
             ttl: 120,
             record: Record::A(A {
                 address: Ipv4Addr::new(192, 168, 1, 100),
@@ -469,7 +684,7 @@ mod tests {
 
         let buffer = buffer.into_inner();
 
-        let (_, parsed_response) = Response::<1, 4, 4>::parse(buffer).unwrap();
+        let parsed_response = Response::<1, 4, 4>::parse(buffer).unwrap();
 
         assert_eq!(response, parsed_response);
     }
@@ -80,8 +80,12 @@
 //! the IO and time. See `examples/myservice.rs` for a full working
 //! example.
 //!
+//! `Server::handle()` is `Server::handle_input()` followed by
+//! `Server::poll_output()`. One input can lead to several outputs,
+//! so keep polling until the server gives back `Output::Timeout`.
+//!
 //! ```no_run
-//! use opslag::{Time, Input, Output, Server, Cast, GROUP_SOCK_V4};
+//! use opslag::{Time, Input, Output, Server, Cast};
 //! use std::time::Duration;
 //! use std::io::ErrorKind;
 //! use std::net::{SocketAddr, UdpSocket};
@@ -107,17 +111,24 @@
 //! let mut input = Input::Timeout(next_timeout);
 //!
 //! loop {
+//!     // Anything but Output::Timeout might be followed by more output,
+//!     // in which case we come straight back with Input::Timeout.
+//!     next_timeout = now();
+//!
 //!     match server.handle(input, &mut output) {
 //!         Output::Packet(n, cast) => {
 //!             // Send a packet to the give destination.
 //!             let to_send = &output[..n];
 //!
 //!             let target = match cast {
-//!                 Cast::Multi { .. } => SocketAddr::V4(GROUP_SOCK_V4),
+//!                 Cast::Multi { target, .. } => target,
 //!                 Cast::Uni { target, .. } => target,
 //!             };
 //!
-//!             sock.send_to(to_send, target).unwrap();
+//!             // The socket is IPv4 only.
+//!             if target.is_ipv4() {
+//!                 sock.send_to(to_send, target).unwrap();
+//!             }
 //!         }
 //!         Output::Timeout(time) => {
 //!             // Next time the server expects a handle(Input::Timeout).
@@ -127,6 +138,26 @@
 //!             // A discovered remote service.
 //!             println!("Remote: {:#?}", service);
 //!         }
+//!         Output::RemoteLost(service) => {
+//!             // A discovered remote service went away.
+//!             println!("Lost: {:#?}", service);
+//!         }
+//!         Output::Renamed(service) => {
+//!             // Our instance name was taken, and the service got a new one.
+//!             println!("Renamed: {:#?}", service);
+//!         }
+//!         Output::ServiceType(service_type) => {
+//!             // A service type found on the network.
+//!             println!("Service type: {}", service_type);
+//!         }
+//!         Output::Resolved(hostname, ip) => {
+//!             // An address of a host name we resolve.
+//!             println!("Resolved: {} {}", hostname, ip);
+//!         }
+//!         Output::Overflow(needed) => {
+//!             // The output buffer is too small for a packet.
+//!             println!("Overflow, needs: {}", needed);
+//!         }
 //!     }
 //!
 //!     // Check how long until the next timeout.
@@ -189,12 +220,18 @@
 //! it will send one packet for each distinct ip/netmask pair it finds.
 //!
 //! When sending a packet, [`Cast::Multi`] and [`Cast::Uni`] both contain a
-//! `from` address. This address is used to determine which socket to send the packet
-//! from. For incoming requests, the ip/netmask pair is used to determine which services
-//! are relevant to consider.
-//!
-//! If you want the same service to appear on two separate interfaces/ip, you declare
-//! the same [`ServiceInfo`] twice, with different ip/netmasks.
+//! `from` address, and a `target` address. The `from` address is used to determine
+//! which socket to send the packet from. For multicast the target is [`GROUP_SOCK_V4`]
+//! or [`GROUP_SOCK_V6`] depending on the family of `from`. For incoming requests, the
+//! ip/netmask pair is used to determine which services are relevant to consider.
+//!
+//! A host with several addresses, like both IPv4 and IPv6, or one address per interface,
+//! is declared once with an extra [`add_address`][ServiceInfo::add_address()] for each
+//! further address. The service is then advertised from each address, and the answers
+//! only carry the addresses valid on the network sent to. Addresses of the other family
+//! go along as additional records.
+//!
+//! Alternatively, the same [`ServiceInfo`] can be declared twice, with different ip/netmasks.
 
 #![cfg_attr(not(feature = "std"), no_std)]
 #![forbid(unsafe_code)]
@@ -216,14 +253,20 @@ mod log_poly;
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
-use core::net::{Ipv4Addr, SocketAddrV4};
+use core::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
 
 #[allow(missing_docs)]
 #[doc(hidden)]
 pub mod dns;
 
+mod string;
 mod vec;
 
+mod cache;
+mod config;
+mod rng;
+pub use config::ServerConfig;
+
 mod service_info;
 pub use service_info::ServiceInfo;
 
@@ -231,9 +274,11 @@ mod server;
 pub use server::{Cast, Input, Output, Server};
 
 mod time;
+mod txt;
 mod writer;
 
 pub use time::Time;
+pub use txt::Txt;
 
 #[cfg(feature = "defmt")]
 pub(crate) mod format;
@@ -247,8 +292,11 @@ pub const GROUP_ADDR_V4: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
 /// Socket address combining multicast address/port.
 pub const GROUP_SOCK_V4: SocketAddrV4 = SocketAddrV4::new(GROUP_ADDR_V4, MDNS_PORT);
 
-// pub const GROUP_ADDR_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
-// pub const GROUP_SOCK_V6: SocketAddrV6 = SocketAddrV6::new(GROUP_ADDR_V6, MDNS_PORT, 0, 0);
+/// Standard IPv6 multicast address for mDNS (ff02::fb).
+pub const GROUP_ADDR_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+
+/// Socket address combining IPv6 multicast address/port.
+pub const GROUP_SOCK_V6: SocketAddrV6 = SocketAddrV6::new(GROUP_ADDR_V6, MDNS_PORT, 0, 0);
 
 #[cfg(all(feature = "std", test))]
 mod test {
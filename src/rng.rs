@@ -0,0 +1,44 @@
+use core::net::IpAddr;
+
+/// Pseudo random numbers for timing jitter (SplitMix64).
+///
+/// Not for anything security related, only to keep nodes from sending at the
+/// exact same time.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    /// Mix something node specific into the state (FNV-1a).
+    pub fn mix(&mut self, data: &[u8]) {
+        for b in data {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn mix_ip(&mut self, ip: IpAddr) {
+        match ip {
+            IpAddr::V4(v4) => self.mix(&v4.octets()),
+            IpAddr::V6(v6) => self.mix(&v6.octets()),
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in the range `min..=max`.
+    pub fn between(&mut self, min: u64, max: u64) -> u64 {
+        if max <= min {
+            return min;
+        }
+        let span = max - min;
+        min + self.next_u64() % span.saturating_add(1)
+    }
+}
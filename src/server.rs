@@ -1,25 +1,50 @@
+use core::cmp::Ordering;
 use core::net::{IpAddr, SocketAddr};
 
-use crate::dns::{Flags, Label, Message, QClass, QType, Query, Request, Response};
+use crate::cache::{Cache, CacheEvent};
+use crate::dns::{
+    Answer, Flags, Label, Message, OwnedLabel, QClass, QType, Query, Record, Request, Response,
+};
+use crate::rng::Rng;
+use crate::service_info::SERVICE_TYPES;
 use crate::time::Time;
 use crate::vec::Vec;
 use crate::writer::Writer;
-use crate::ServiceInfo;
+use crate::{ServerConfig, ServiceInfo, GROUP_SOCK_V4, GROUP_SOCK_V6};
 
 /// A server for broadcasting/discovering peers.
 ///
 /// * `QLEN` - Max number of queries in a single mDNS packet. Only used if not **std**.
 ///   Typically 4 for SRV, PTR, TXT and A (or AAAA).
 /// * `ALEN` - Max number of answers in a single mDNS packet. Only used if not **std**.
-///   Typically 4 for SRV, PTR, TXT and A (or AAAA).
+///   Typically 4 for SRV, PTR, TXT and A (or AAAA). The same max is used for the
+///   authority and additional sections.
 /// * `LLEN` - Max number of segments for a parsed Label.
 ///   All services have max 4 segments: martin_test._myservice._udp.local.
-/// * `SLEN` - Capacity for service infos and query targets in the [`Server`].
+/// * `SLEN` - Capacity for service infos, query targets and distinct local addresses in the
+///   [`Server`].
 /// * `LK`   – List size for DNS label compression. 10 is a good value.
+/// * `CLEN` - Capacity for the cache of discovered remote services. Only used if not **alloc**.
+///   Defaults to 8. Remotes that don't fit are reported on every response they are in, up
+///   to 2 per response.
+/// * `SUBLEN` - Max number of subtypes of a service, see [`ServiceInfo::add_subtype`]. Only
+///   used if not **alloc**. Defaults to 0, and remotes are then reported without subtypes.
 ///
 /// Specifying too small QLEN, ALEN, LLEN or SLEN does not make the server fail, but rather
 /// reject messages that can't be parsed.
 ///
+/// Before a service is advertised, the server probes the network to make sure the
+/// instance name is unique (RFC 6762 8.1). If another host already uses the name,
+/// the service is renamed (`martin_test` -> `martin_test (2)`) and the new name is
+/// reported via [`Output::Renamed`].
+///
+/// The timing of probes, announcements and queries is set with [`ServerConfig`].
+///
+/// Discovered remote services are cached until their records expire. [`Output::Remote`]
+/// is only emitted for services that are new or changed, and [`Output::RemoteLost`] when
+/// the records expire or the remote says goodbye. Records close to expiry are
+/// refreshed with queries at 80%, 85%, 90% and 95% of their TTL.
+///
 /// ```
 /// use opslag::{Server, ServiceInfo};
 ///
@@ -46,17 +71,40 @@ pub struct Server<
     const LLEN: usize,
     const SLEN: usize,
     const LK: usize,
+    const CLEN: usize = 8,
+    const SUBLEN: usize = 0,
 > {
+    config: ServerConfig,
     last_now: Time,
-    services: Vec<ServiceInfo<'a, LLEN>, SLEN>,
+    services: Vec<LocalService<'a, LLEN, SUBLEN>, SLEN>,
+    /// Withdrawn records waiting for their goodbye. Kept apart from the services, so
+    /// a full `services` doesn't stop the goodbyes.
+    goodbyes: Vec<LocalService<'a, LLEN, SUBLEN>, SLEN>,
     query_targets: Vec<QueryTarget<'a, LLEN>, SLEN>,
     local_ips: Vec<LocalIp, SLEN>,
     next_advertise: Time,
     next_advertise_idx: usize,
-    next_query: Time,
+    /// Number of answers advertised so far from the current local ip, when they
+    /// don't all fit in one packet.
+    advertise_sent: usize,
+    /// Number of advertise rounds since the last change of the services.
+    announcements: u8,
     next_query_idx: usize,
     txid_query: u16,
     next_txid: u16,
+    cache: Cache<LLEN, CLEN, SUBLEN>,
+    rng: Rng,
+    responses: Vec<PendingResponse, SLEN>,
+    /// Known answers left to send after a truncated query.
+    known_answers: Option<KnownAnswers<QLEN>>,
+    /// Remotes that don't fit in the cache, not yet reported.
+    uncached: Vec<ServiceInfo<'static, LLEN, SUBLEN>, MAX_UNCACHED>,
+    /// Service types found by [`Server::query_service_types`].
+    service_types: Vec<OwnedLabel, CLEN>,
+    /// Number of the found service types reported so far.
+    service_types_reported: usize,
+    /// Addresses of resolved host names, not yet reported.
+    resolved: Vec<(OwnedLabel, IpAddr), CLEN>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -66,12 +114,117 @@ struct LocalIp {
 }
 
 struct QueryTarget<'a, const LLEN: usize> {
-    service_type: Label<'a, LLEN>,
+    /// Service type to browse for, or host name to resolve.
+    name: Label<'a, LLEN>,
+    /// Resolving a host name, see [`Server::resolve_host`].
+    host: bool,
     local_ip: LocalIp,
+    backoff: Backoff,
+}
+
+/// A response to a query, waiting to be sent.
+///
+/// Responses are kept as the records to send rather than as a packet, and the ones for the
+/// same destination are aggregated into one packet when sent (RFC 6762 6.3).
+struct PendingResponse {
+    /// When to send. Multicast responses with shared records are held back by a random
+    /// delay, since other hosts might answer the same query (RFC 6762 6).
+    at: Time,
+    /// Local ip to send from.
+    from: IpAddr,
+    /// The host that asked, which might follow up with more known answers.
+    querier: IpAddr,
+    to: Destination,
+    /// The query answered. Legacy unicast responses repeat it.
+    qname: OwnedLabel,
+    qtype: QType,
+    qclass: QClass,
+    /// The service to respond with. Copied, since the service can be renamed.
+    instance_name: OwnedLabel,
+    records: Records,
+}
+
+/// Where to send a response.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Destination {
+    Multicast,
+    /// Straight to a querier that set the QU bit (RFC 6762 5.4).
+    Unicast(SocketAddr),
+    /// Straight to a querier not using port 5353, which is a simple resolver rather
+    /// than a full mDNS implementation. The transaction id is echoed (RFC 6762 6.7).
+    Legacy(SocketAddr, u16),
+}
+
+/// A query with more known answers than fit in one packet.
+struct KnownAnswers<const QLEN: usize> {
+    local: LocalIp,
+    /// Names of the PTR queries.
+    names: Vec<OwnedLabel, QLEN>,
+    /// Number of known answers sent so far.
+    sent: usize,
+}
+
+/// Which records of a service to respond with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Records(u8);
+
+/// Schedule for continuous queries, with an interval that doubles for each query
+/// (RFC 6762 5.2).
+#[derive(Clone, Copy)]
+struct Backoff {
+    next: Time,
+    interval: u64,
+    /// No query sent yet. The first one asks for unicast responses (RFC 6762 5.4).
+    first: bool,
 }
 
-const ADVERTISE_INTERVAL: u64 = 15_000;
-const QUERY_INTERVAL: u64 = 19_000;
+struct LocalService<'a, const LLEN: usize, const SUBLEN: usize> {
+    info: ServiceInfo<'a, LLEN, SUBLEN>,
+    state: ServiceState,
+    /// Queries for other instances of the service type.
+    query: Backoff,
+    /// Set when the service has been renamed, but not yet reported.
+    renamed: bool,
+    /// When the records were last multicast.
+    multicast: Option<Time>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ServiceState {
+    /// Probing to make sure the instance name is unique.
+    Probing {
+        /// Number of probes sent so far.
+        sent: u8,
+        /// Time for the next probe (or announcement once all probes are sent).
+        next: Time,
+        /// Number of the service addresses the current probe has been sent from.
+        from: usize,
+    },
+    /// Probing is done and the service is advertised.
+    Announced,
+    /// The service is withdrawn and is removed once the goodbye is sent.
+    Goodbye {
+        /// Number of the service addresses the goodbye has been sent from.
+        sent: usize,
+    },
+}
+
+const PROBE_INTERVAL: u64 = 250;
+const PROBE_COUNT: u8 = 3;
+// Time to wait before probing again after losing a simultaneous probe tie-break.
+const PROBE_DEFER: u64 = 1000;
+// Time to wait for more known answers after a truncated query (RFC 6762 7.2).
+const TRUNCATED_DELAY_MIN: u64 = 400;
+const TRUNCATED_DELAY_MAX: u64 = 500;
+// Records multicast within a quarter of their TTL may be unicast to QU questions. The
+// TTL of the SRV, TXT and address records is 120s (RFC 6762 5.4).
+const UNICAST_WINDOW: u64 = 120_000 / 4;
+const MDNS_PORT: u16 = 5353;
+// Max TTL in responses to legacy queriers (RFC 6762 6.7).
+const LEGACY_TTL: u32 = 10;
+// Max remotes that don't fit in the cache, waiting to be reported. Only used if not alloc,
+// since the cache is never full with alloc.
+const MAX_UNCACHED: usize = 2;
 
 /// How to cast outgoing packets.
 #[derive(Debug)]
@@ -80,6 +233,9 @@ pub enum Cast {
     Multi {
         /// Send from this ip address.
         from: IpAddr,
+        /// The multicast group to send to. [`GROUP_SOCK_V4`] or [`GROUP_SOCK_V6`]
+        /// depending on the family of `from`.
+        target: SocketAddr,
     },
     /// Unicast to specific socket address.
     Uni {
@@ -90,6 +246,17 @@ pub enum Cast {
     },
 }
 
+impl Cast {
+    /// Multicast from `from` to the mDNS group of the same family.
+    fn multi(from: IpAddr) -> Self {
+        let target = match from {
+            IpAddr::V4(_) => SocketAddr::V4(GROUP_SOCK_V4),
+            IpAddr::V6(_) => SocketAddr::V6(GROUP_SOCK_V6),
+        };
+        Cast::Multi { from, target }
+    }
+}
+
 /// Input to [`Server`].
 #[derive(Debug)]
 pub enum Input<'x> {
@@ -104,11 +271,11 @@ pub enum Input<'x> {
 }
 
 /// Output from the [`Server`].
-pub enum Output<'x, const LLEN: usize, const SLEN: usize> {
+pub enum Output<'x, const LLEN: usize, const SLEN: usize, const SUBLEN: usize = 0> {
     /// A packet to send somewhere.
     ///
-    /// The data is in the buffer given to [`Server::handle`] and the amount of the
-    /// buffer use is the first argument of the tuple.å
+    /// The data is in the buffer given to [`Server::poll_output`] and the amount of the
+    /// buffer use is the first argument of the tuple.
     Packet(usize, Cast),
 
     /// Next time the service expects a timeout.
@@ -117,7 +284,179 @@ pub enum Output<'x, const LLEN: usize, const SLEN: usize> {
     Timeout(Time),
 
     /// The [`Server`] discovered a remote instance of a declared service type.
-    Remote(ServiceInfo<'x, LLEN>),
+    ///
+    /// This is emitted once for new remotes, and again if the remote changes. The
+    /// [`ServiceInfo`] doesn't borrow the packet, so it can be kept around.
+    Remote(ServiceInfo<'x, LLEN, SUBLEN>),
+
+    /// A previously discovered remote went away.
+    ///
+    /// Either the remote said goodbye, or its records expired.
+    RemoteLost(ServiceInfo<'x, LLEN, SUBLEN>),
+
+    /// A local service was renamed because another host uses the same instance name.
+    ///
+    /// The [`ServiceInfo`] has the new instance name. Persist it to keep using the same
+    /// name the next time the service starts.
+    Renamed(ServiceInfo<'x, LLEN, SUBLEN>),
+
+    /// A service type found by [`Server::query_service_types`].
+    ///
+    /// Each service type is reported once.
+    ServiceType(OwnedLabel),
+
+    /// An address of a host name given to [`Server::resolve_host`].
+    ///
+    /// A host with several addresses gives one output per address.
+    Resolved(OwnedLabel, IpAddr),
+
+    /// A packet did not fit in the buffer given to [`Server::poll_output`].
+    ///
+    /// The packet is dropped. The value is the buffer size it needs. Poll again to
+    /// continue with the next output.
+    Overflow(usize),
+}
+
+impl Records {
+    const NONE: Records = Records(0);
+    /// The PTR record of the service type, for the service type enumeration.
+    const SERVICE_TYPE: Records = Records(16);
+
+    fn of(atype: QType) -> Records {
+        match atype {
+            QType::PTR => Records(1),
+            QType::SRV => Records(2),
+            QType::TXT => Records(4),
+            QType::A => Records(8),
+            QType::AAAA => Records(32),
+            _ => Records::NONE,
+        }
+    }
+
+    fn with(self, other: Records) -> Records {
+        Records(self.0 | other.0)
+    }
+
+    fn contains(self, atype: QType) -> bool {
+        let bit = Records::of(atype);
+        bit != Records::NONE && self.has(bit)
+    }
+
+    fn has(self, other: Records) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Backoff {
+    fn new(next: Time, config: &ServerConfig) -> Self {
+        Backoff {
+            next,
+            interval: config.query_interval,
+            first: true,
+        }
+    }
+
+    fn is_due(&self, now: Time) -> bool {
+        now >= self.next
+    }
+
+    fn sent(&mut self, now: Time, config: &ServerConfig) {
+        self.first = false;
+        self.next = now + self.interval;
+        self.interval = self
+            .interval
+            .saturating_mul(2)
+            .min(config.query_interval_max);
+    }
+}
+
+impl<'a, const LLEN: usize, const SUBLEN: usize> LocalService<'a, LLEN, SUBLEN> {
+    /// All addresses of the service.
+    fn local_ips(&self) -> impl Iterator<Item = LocalIp> + '_ {
+        self.info
+            .networks()
+            .map(|(addr, mask)| LocalIp { addr, mask })
+    }
+
+    /// Whether the service has an address on the same network as `ip`.
+    fn is_on(&self, ip: IpAddr) -> bool {
+        self.info
+            .networks()
+            .any(|(addr, mask)| is_same_network(addr, mask, ip))
+    }
+
+    fn is_announced(&self) -> bool {
+        self.state == ServiceState::Announced
+    }
+
+    /// Whether the records were multicast recently enough to unicast them.
+    fn is_multicast_recently(&self, now: Time) -> bool {
+        self.multicast.is_some_and(|t| now < t + UNICAST_WINDOW)
+    }
+
+    /// The address to send the next goodbye from, if any is left.
+    fn goodbye_from(&self) -> Option<LocalIp> {
+        match self.state {
+            ServiceState::Goodbye { sent } => self.local_ips().nth(sent),
+            _ => None,
+        }
+    }
+
+    fn probe_due(&self, now: Time) -> bool {
+        matches!(self.state, ServiceState::Probing { next, .. } if now >= next)
+    }
+
+    /// The address to send the next probe from, if one is due.
+    ///
+    /// Each probe goes out from every address of the service, since the instance name
+    /// must be unique on all the networks it is announced on.
+    fn probe_from(&self, now: Time) -> Option<LocalIp> {
+        match self.state {
+            ServiceState::Probing { sent, next, from } if sent < PROBE_COUNT && now >= next => {
+                self.local_ips().nth(from)
+            }
+            _ => None,
+        }
+    }
+
+    /// The records we propose for the instance name while probing.
+    fn probe_answers(&self) -> [Answer<'_, LLEN>; 2] {
+        [self.info.srv_answer(false), self.info.txt_answer(false)]
+    }
+
+    /// The goodbye for the records of the service, or `None` if it can be dropped
+    /// right away, since services that never were announced need no goodbye.
+    fn into_goodbye(self) -> Option<Self> {
+        match self.state {
+            ServiceState::Probing { .. } => None,
+            ServiceState::Announced | ServiceState::Goodbye { .. } => Some(LocalService {
+                state: ServiceState::Goodbye { sent: 0 },
+                renamed: false,
+                multicast: None,
+                ..self
+            }),
+        }
+    }
+
+    /// Start over with probing. Used both for conflicts and for lost tie-breaks.
+    fn restart_probing(&mut self, at: Time) {
+        self.state = ServiceState::Probing {
+            sent: 0,
+            next: at,
+            from: 0,
+        };
+    }
+}
+
+impl PendingResponse {
+    /// Whether `answer` is one of the records to respond with.
+    ///
+    /// Of the PTR records, only the one asked for. A query for a subtype doesn't get
+    /// the PTR records of the type or other subtypes (RFC 6763 7.1).
+    fn is_wanted<const LLEN: usize>(&self, answer: &Answer<'_, LLEN>) -> bool {
+        self.records.contains(answer.atype)
+            && (answer.atype != QType::PTR || answer.name == self.qname)
+    }
 }
 
 impl<
@@ -127,57 +466,100 @@ impl<
         const LLEN: usize,
         const SLEN: usize,
         const LK: usize,
-    > Server<'a, QLEN, ALEN, LLEN, SLEN, LK>
+        const CLEN: usize,
+        const SUBLEN: usize,
+    > Server<'a, QLEN, ALEN, LLEN, SLEN, LK, CLEN, SUBLEN>
 {
     /// Creates a new server instance.
     pub fn new(
-        iter: impl Iterator<Item = ServiceInfo<'a, LLEN>>,
-    ) -> Server<'a, QLEN, ALEN, LLEN, SLEN, LK> {
-        let mut services = Vec::new();
-        services.extend(iter);
+        iter: impl Iterator<Item = ServiceInfo<'a, LLEN, SUBLEN>>,
+    ) -> Server<'a, QLEN, ALEN, LLEN, SLEN, LK, CLEN, SUBLEN> {
+        Self::new_with_config(iter, ServerConfig::default())
+    }
 
-        let mut local_ips = Vec::new();
-        for s in services.iter() {
-            let loc = LocalIp {
-                addr: s.ip_address(),
-                mask: s.netmask(),
+    /// Creates a new server instance with other timing than the default.
+    ///
+    /// See [`ServerConfig`].
+    pub fn new_with_config(
+        iter: impl Iterator<Item = ServiceInfo<'a, LLEN, SUBLEN>>,
+        config: ServerConfig,
+    ) -> Server<'a, QLEN, ALEN, LLEN, SLEN, LK, CLEN, SUBLEN> {
+        let mut services = Vec::new();
+        for info in iter {
+            let service = LocalService {
+                info,
+                state: ServiceState::Probing {
+                    sent: 0,
+                    next: Time::from_millis(config.probe_delay),
+                    from: 0,
+                },
+                query: Backoff::new(Time::from_millis(config.query_delay), &config),
+                renamed: false,
+                multicast: None,
             };
-            let has_ip = local_ips.contains(&loc);
-            if !has_ip {
-                // unwrap: this should be fine since local_ips is as long as services.
-                local_ips.push(loc).unwrap();
+            if services.push(service).is_err() {
+                warn!("More services than SLEN: {}", SLEN);
+                break;
             }
         }
 
-        let has_services = !services.is_empty();
-
-        Server {
+        let mut server = Server {
+            config,
             last_now: Time::from_millis(0),
             services,
+            goodbyes: Vec::new(),
             query_targets: Vec::new(),
-            local_ips,
-            next_advertise: if has_services {
-                Time::from_millis(3000)
-            } else {
-                Time::from_millis(u64::MAX)
-            },
+            local_ips: Vec::new(),
+            // Advertising starts once probing is done.
+            next_advertise: Time::from_millis(u64::MAX),
             next_advertise_idx: 0,
-            next_query: if has_services {
-                Time::from_millis(5000)
-            } else {
-                Time::from_millis(u64::MAX)
-            },
+            advertise_sent: 0,
+            announcements: 0,
             next_query_idx: 0,
             txid_query: 0,
             next_txid: 1,
+            cache: Cache::new(),
+            rng: Rng::new(config.seed),
+            responses: Vec::new(),
+            known_answers: None,
+            uncached: Vec::new(),
+            service_types: Vec::new(),
+            service_types_reported: 0,
+            resolved: Vec::new(),
+        };
+
+        // Nodes on the default seed still get jitter of their own.
+        for s in server.services.iter() {
+            for segment in s.info.instance_name().iter() {
+                server.rng.mix(segment.as_bytes());
+            }
+        }
+
+        server.update_local_ips();
+
+        // Nodes started together don't probe and query at the same time.
+        for s in server.services.iter_mut() {
+            if let ServiceState::Probing { next, .. } = &mut s.state {
+                *next = *next + server.rng.between(0, config.probe_jitter);
+            }
+            s.query.next = s.query.next + server.rng.between(config.jitter_min, config.jitter_max);
         }
+
+        server
     }
 
     /// Register a service type to query for without advertising.
     ///
     /// This enables discovery-only mode for the given service type: the server will
     /// send PTR queries to discover remote instances but will not advertise any local
-    /// service. The first query goes out immediately (on the next timeout).
+    /// service. The first query goes out after a random delay of
+    /// [`jitter_min`][ServerConfig::jitter_min] to [`jitter_max`][ServerConfig::jitter_max],
+    /// 20-120ms by default (RFC 6762 5.2).
+    ///
+    /// The service type can be a subtype, like `_printer._sub._http._tcp.local`, to only
+    /// discover the instances that have it (RFC 6763 7.1). They are reported with the
+    /// parent type as [`ServiceInfo::service_type`] and the subtype among
+    /// [`ServiceInfo::subtypes`], which needs `SUBLEN` of at least 1 without **alloc**.
     ///
     /// ```
     /// use opslag::Server;
@@ -195,413 +577,4082 @@ impl<
         ip: impl Into<IpAddr>,
         netmask: impl Into<IpAddr>,
     ) {
+        self.add_query_target(service_type, false, ip.into(), netmask.into());
+    }
+
+    fn add_query_target(&mut self, name: &'a str, host: bool, ip: IpAddr, netmask: IpAddr) {
         let local_ip = LocalIp {
-            addr: ip.into(),
-            mask: netmask.into(),
+            addr: ip,
+            mask: netmask,
         };
 
+        if !self.local_ips.contains(&local_ip) && self.local_ips.push(local_ip).is_ok() {
+            self.rng.mix_ip(ip);
+        }
+
         let already_exists = self
             .query_targets
             .iter()
-            .any(|qt| qt.local_ip == local_ip && qt.service_type == service_type);
+            .any(|qt| qt.local_ip == local_ip && qt.host == host && qt.name == name);
 
         if !already_exists {
+            let backoff = self.first_query();
             let _ = self.query_targets.push(QueryTarget {
-                service_type: Label::new(service_type),
+                name: Label::new(name),
+                host,
                 local_ip,
+                backoff,
             });
         }
-
-        if !self.local_ips.contains(&local_ip) {
-            let _ = self.local_ips.push(local_ip);
-        }
-
-        // Fire the next query immediately.
-        self.next_query = self.last_now;
     }
 
-    fn poll_timeout(&self) -> Time {
-        if self.services.is_empty() {
-            self.next_query
-        } else {
-            self.next_advertise.min(self.next_query)
-        }
+    /// Browse for the service types on the network (RFC 6763 9).
+    ///
+    /// Queries for `_services._dns-sd._udp.local` like [`Server::query`] does for a
+    /// service type. Each service type found is reported once as [`Output::ServiceType`].
+    /// Without **alloc**, at most `CLEN` service types are kept track of.
+    ///
+    /// ```
+    /// use opslag::Server;
+    ///
+    /// let mut server: Server<4, 4, 4, 1, 10> = Server::new(std::iter::empty());
+    /// server.query_service_types([192, 168, 0, 1], [255, 255, 255, 0]);
+    /// ```
+    pub fn query_service_types(&mut self, ip: impl Into<IpAddr>, netmask: impl Into<IpAddr>) {
+        self.query(SERVICE_TYPES, ip, netmask);
     }
 
-    /// Handle some input and produce output.
+    /// Resolve a host name, like `nugget.local`, to its addresses.
     ///
-    /// You can send [`Input::Timeout`] whenenver. The `buffer` is for outgoing packets.
-    /// Upon [`Output::Packet`] the buffer will be filled to some point with data to transmit.
-    pub fn handle<'x>(&mut self, input: Input<'x>, buffer: &mut [u8]) -> Output<'x, LLEN, SLEN> {
-        match input {
-            Input::Timeout(now) => self.handle_timeout(now, buffer),
-            Input::Packet(data, from) => self.handle_packet(data, from, buffer),
-        }
+    /// Queries for the A and AAAA records of the host, with the same backoff as
+    /// [`Server::query`], until the host answers. Each address is reported as
+    /// [`Output::Resolved`]. This works for any host on the network, not only the ones
+    /// advertising a service. Call again to resolve the host anew.
+    ///
+    /// ```
+    /// use opslag::Server;
+    ///
+    /// let mut server: Server<4, 4, 4, 1, 10> = Server::new(std::iter::empty());
+    /// server.resolve_host("nugget.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+    /// ```
+    pub fn resolve_host(
+        &mut self,
+        hostname: &'a str,
+        ip: impl Into<IpAddr>,
+        netmask: impl Into<IpAddr>,
+    ) {
+        self.add_query_target(hostname, true, ip.into(), netmask.into());
     }
 
-    fn handle_timeout(&mut self, now: Time, buffer: &mut [u8]) -> Output<'static, LLEN, SLEN> {
-        self.last_now = now;
-
-        if !self.services.is_empty() && now >= self.next_advertise {
-            let send_from = self.local_ips[self.next_advertise_idx];
-
-            let ret = self.do_advertise(buffer, send_from);
-
-            self.next_advertise_idx += 1;
-
-            if self.next_advertise_idx == self.local_ips.len() {
-                self.next_advertise_idx = 0;
-                self.next_advertise = now + ADVERTISE_INTERVAL;
-            }
-
-            ret
-        } else if !self.local_ips.is_empty() && now >= self.next_query {
-            let send_from = self.local_ips[self.next_query_idx];
-
-            let ret = self.do_query(buffer, send_from);
-
-            self.next_query_idx += 1;
-
-            if self.next_query_idx == self.local_ips.len() {
-                self.next_query_idx = 0;
-                self.next_query = now + QUERY_INTERVAL;
-            }
+    /// Add a service to advertise.
+    ///
+    /// The service is probed for a unique instance name and then announced, like the
+    /// services given to [`Server::new`]. Probing starts on the next call to
+    /// [`Server::handle`].
+    ///
+    /// Returns `false` if there already is a service with the same instance name, or if
+    /// the service doesn't fit in `SLEN`.
+    ///
+    /// ```
+    /// use opslag::{Server, ServiceInfo};
+    ///
+    /// let info = ServiceInfo::<4>::new(
+    ///     "_my-service._udp.local",
+    ///     "martin_test",
+    ///     "nugget.local",
+    ///     [192, 168, 0, 3],
+    ///     [255, 255, 255, 0],
+    ///     1234,
+    /// );
+    ///
+    /// let mut server: Server<4, 4, 4, 1, 10> = Server::new(std::iter::empty());
+    /// assert!(server.add_service(info.clone()));
+    /// assert!(!server.add_service(info));
+    /// ```
+    pub fn add_service(&mut self, info: ServiceInfo<'a, LLEN, SUBLEN>) -> bool {
+        let exists = self
+            .services
+            .iter()
+            .any(|s| s.info.instance_name() == info.instance_name());
 
-            ret
-        } else {
-            Output::Timeout(self.poll_timeout())
+        if exists {
+            return false;
         }
-    }
 
-    fn next_txid(&mut self) -> u16 {
-        let x = self.next_txid;
-        self.next_txid = self.next_txid.wrapping_add(1);
-        x
-    }
+        for segment in info.instance_name().iter() {
+            self.rng.mix(segment.as_bytes());
+        }
 
-    fn do_advertise(&mut self, buffer: &mut [u8], local: LocalIp) -> Output<'static, LLEN, SLEN> {
-        let mut response: Response<QLEN, ALEN, LLEN> = Response {
-            id: 0,
-            flags: Flags::standard_response(),
-            queries: Vec::new(),
-            answers: Vec::new(),
+        let service = LocalService {
+            info,
+            state: ServiceState::Probing {
+                sent: 0,
+                next: self.last_now + self.probe_jitter(),
+                from: 0,
+            },
+            // Look for other instances of the service type right away.
+            query: self.first_query(),
+            renamed: false,
+            multicast: None,
         };
 
-        let to_consider = self
-            .services
-            .iter()
-            .filter(|s| s.ip_address() == local.addr && s.netmask() == local.mask);
-
-        for service in to_consider {
-            response
-                .answers
-                .extend(service.as_answers(QClass::Multicast));
+        if self.services.push(service).is_err() {
+            return false;
         }
 
-        debug!("Advertise response (from {}): {:?}", local.addr, response);
-
-        let mut buf = Writer::<LK>::new(buffer);
-
-        response.serialize(&mut buf);
+        self.update_local_ips();
 
-        Output::Packet(buf.len(), Cast::Multi { from: local.addr })
+        true
     }
 
-    fn do_query(&mut self, buffer: &mut [u8], local: LocalIp) -> Output<'static, LLEN, SLEN> {
-        let mut request: Request<QLEN, LLEN> = Request {
-            id: self.next_txid(),
-            flags: Flags::standard_request(),
-            queries: Vec::new(),
-        };
-
-        self.txid_query = request.id;
-
-        let to_consider = self
+    /// Change an advertised service.
+    ///
+    /// Replaces the service with the given instance name (i.e.
+    /// `martin_test._my-service._udp.local`) with `info`. If the instance name stays the
+    /// same, the new records are announced right away, and goodbyes are sent for the old
+    /// records no longer in use, like a changed port or address (RFC 6762 8.4). A new
+    /// instance name is probed for first, like for [`Server::add_service`].
+    ///
+    /// Returns `false` if there was no such service, or no room for the goodbye of the
+    /// old records.
+    pub fn update_service(
+        &mut self,
+        instance_name: &str,
+        info: ServiceInfo<'a, LLEN, SUBLEN>,
+    ) -> bool {
+        let Some(idx) = self
             .services
             .iter()
-            .filter(|s| s.ip_address() == local.addr && s.netmask() == local.mask);
+            .position(|s| s.info.instance_name() == instance_name)
+        else {
+            return false;
+        };
 
-        for service in to_consider {
-            let query = Query {
-                name: service.service_type().clone(),
-                qtype: QType::PTR,
-                qclass: QClass::IN,
-            };
-            let _ = request.queries.push(query);
-        }
+        let now = self.last_now;
+        let service = &mut self.services[idx];
 
-        for qt in self.query_targets.iter() {
-            if qt.local_ip == local {
-                let query = Query {
-                    name: qt.service_type.clone(),
-                    qtype: QType::PTR,
-                    qclass: QClass::IN,
-                };
-                let _ = request.queries.push(query);
-            }
+        let was_announced = service.is_announced();
+        if was_announced && self.goodbyes.is_full() {
+            warn!(
+                "No room for goodbye of updated service: {:?}",
+                instance_name
+            );
+            return false;
         }
+        let same_name = service.info.instance_name() == info.instance_name();
 
-        debug!("Send request (from {}): {:?}", local.addr, request);
-
-        let mut buf = Writer::<LK>::new(buffer);
-        request.serialize(&mut buf);
+        let old = core::mem::replace(&mut service.info, info);
 
-        Output::Packet(buf.len(), Cast::Multi { from: local.addr })
-    }
+        // The name is already ours, no need to probe again.
+        let reannounce = was_announced && same_name;
 
-    fn handle_packet<'x>(
-        &mut self,
-        data: &'x [u8],
-        from: SocketAddr,
-        buffer: &mut [u8],
-    ) -> Output<'x, LLEN, SLEN> {
-        match Message::parse(data) {
-            Ok((_, Message::Request(request))) => self.handle_request(request, from, buffer),
-            Ok((_, Message::Response(response))) => self.handle_response(response, from, buffer),
-            Err(_) => Output::Timeout(self.poll_timeout()),
+        if !reannounce {
+            service.restart_probing(now);
         }
-    }
 
-    fn handle_request<'x>(
-        &mut self,
-        request: Request<'x, QLEN, LLEN>,
-        from: SocketAddr,
-        buffer: &mut [u8],
-    ) -> Output<'x, LLEN, SLEN> {
-        if request.queries.is_empty() {
-            return Output::Timeout(self.poll_timeout());
+        if was_announced {
+            let goodbye = LocalService {
+                info: old,
+                state: ServiceState::Goodbye { sent: 0 },
+                query: self.services[idx].query,
+                renamed: false,
+                multicast: None,
+            };
+            // Room was checked above.
+            let _ = self.goodbyes.push(goodbye);
         }
 
-        // Ignore requests from self
-        if request.id == self.txid_query {
-            return Output::Timeout(self.poll_timeout());
+        if reannounce {
+            self.restart_announcements();
         }
 
-        // We check for empty above
-        let qclass = request.queries[0].qclass;
+        self.update_local_ips();
 
-        let queries = request.queries.iter();
+        true
+    }
 
-        let mut answers = Vec::new();
+    /// Withdraw a service.
+    ///
+    /// Removes all services with the given instance name (i.e. `martin_test._my-service._udp.local`).
+    /// Services that have been announced are removed after sending a goodbye packet,
+    /// which tells other hosts to flush the records right away (RFC 6762 10.1). The
+    /// goodbye packets are produced by the following calls to [`Server::handle`].
+    ///
+    /// Returns `false` if there was no such service.
+    ///
+    /// ```
+    /// use opslag::{Server, ServiceInfo};
+    ///
+    /// let info = ServiceInfo::<4>::new(
+    ///     "_my-service._udp.local",
+    ///     "martin_test",
+    ///     "nugget.local",
+    ///     [192, 168, 0, 3],
+    ///     [255, 255, 255, 0],
+    ///     1234,
+    /// );
+    ///
+    /// let mut server: Server<4, 4, 4, 1, 10> = Server::new([info].into_iter());
+    /// assert!(server.remove_service("martin_test._my-service._udp.local"));
+    /// ```
+    pub fn remove_service(&mut self, instance_name: &str) -> bool {
+        let mut found = false;
 
-        for query in queries {
-            for service in self.services.iter() {
-                if query.qtype == QType::PTR
-                    && &query.name == service.service_type()
-                    && is_same_network(service.ip_address(), service.netmask(), from.ip())
-                {
-                    for answer in service.as_answers(qclass) {
-                        if !answers.contains(&answer) {
-                            let _ = answers.push(answer);
-                        }
-                    }
-                }
-            }
+        while let Some(idx) = self
+            .services
+            .iter()
+            .position(|s| s.info.instance_name() == instance_name)
+        {
+            found = true;
+            let service = self.services.remove(idx);
+            self.queue_goodbye(service);
         }
 
-        if answers.is_empty() {
-            return Output::Timeout(self.poll_timeout());
-        }
+        self.update_local_ips();
 
-        debug!("Incoming request: {:?} {:?}", from, request);
+        found
+    }
 
-        let response: Response<QLEN, ALEN, LLEN> = Response {
-            id: request.id,
-            flags: Flags::standard_response(),
-            queries: request.queries,
-            answers,
+    /// An interface address changed, like when moving to another network.
+    ///
+    /// Services and query targets using the address `old` move over to `ip`/`netmask`.
+    /// Services send goodbyes from the old address, and are probed and announced again
+    /// on the new one (RFC 6762 8.3). Remotes discovered on the old network are reported
+    /// with [`Output::RemoteLost`].
+    ///
+    /// Returns `false` if nothing uses the address `old`.
+    ///
+    /// ```
+    /// use opslag::{Server, ServiceInfo};
+    ///
+    /// let info = ServiceInfo::<4>::new(
+    ///     "_my-service._udp.local",
+    ///     "martin_test",
+    ///     "nugget.local",
+    ///     [192, 168, 0, 3],
+    ///     [255, 255, 255, 0],
+    ///     1234,
+    /// );
+    ///
+    /// let mut server: Server<4, 4, 4, 1, 10> = Server::new([info].into_iter());
+    /// assert!(server.change_address([192, 168, 0, 3], [10, 0, 0, 3], [255, 0, 0, 0]));
+    /// ```
+    pub fn change_address(
+        &mut self,
+        old: impl Into<IpAddr>,
+        ip: impl Into<IpAddr>,
+        netmask: impl Into<IpAddr>,
+    ) -> bool {
+        let new = LocalIp {
+            addr: ip.into(),
+            mask: netmask.into(),
+        };
+        self.move_address(old.into(), Some(new))
+    }
+
+    /// An interface address went away.
+    ///
+    /// Services and query targets stop using the address `ip`. Services send goodbyes
+    /// from it, and services without any address left are removed. Remotes discovered on
+    /// the network are reported with [`Output::RemoteLost`].
+    ///
+    /// Returns `false` if nothing uses the address.
+    pub fn remove_address(&mut self, ip: impl Into<IpAddr>) -> bool {
+        self.move_address(ip.into(), None)
+    }
+
+    /// An interface address was added.
+    ///
+    /// Every service gets the address `ip`/`netmask`, and is probed and announced again
+    /// with it (RFC 6762 8.3). Query targets don't follow, use [`Server::query`] to look
+    /// for remotes on the new network.
+    ///
+    /// Returns `false` if no service got the address, because they already have it or
+    /// have no room for it.
+    ///
+    /// ```
+    /// use opslag::{Server, ServiceInfo};
+    ///
+    /// let info = ServiceInfo::<4>::new(
+    ///     "_my-service._udp.local",
+    ///     "martin_test",
+    ///     "nugget.local",
+    ///     [192, 168, 0, 3],
+    ///     [255, 255, 255, 0],
+    ///     1234,
+    /// );
+    ///
+    /// let mut server: Server<4, 4, 4, 1, 10> = Server::new([info].into_iter());
+    /// assert!(server.add_address([10, 0, 0, 3], [255, 0, 0, 0]));
+    /// ```
+    pub fn add_address(&mut self, ip: impl Into<IpAddr>, netmask: impl Into<IpAddr>) -> bool {
+        let ip = ip.into();
+        let netmask = netmask.into();
+
+        let now = self.last_now;
+        let jitter = self.jitter();
+        let mut added = false;
+
+        for s in self.services.iter_mut() {
+            if !s.info.add_address(ip, netmask) {
+                continue;
+            }
+            added = true;
+
+            // Probe and look for remotes on the new network right away.
+            s.restart_probing(now);
+            s.query = Backoff::new(now + jitter, &self.config);
+        }
+
+        self.update_local_ips();
+
+        added
+    }
+
+    fn move_address(&mut self, old: IpAddr, new: Option<LocalIp>) -> bool {
+        let Some(lost) = self.local_ips.iter().find(|l| l.addr == old).copied() else {
+            return false;
+        };
+
+        let now = self.last_now;
+        let jitter = self.jitter();
+
+        for s in self.services.iter_mut() {
+            if !s.info.ip_addresses().any(|ip| ip == old) {
+                continue;
+            }
+
+            let mut info = s.info.clone();
+            match new {
+                Some(new) => info.replace_address(old, new.addr, new.mask),
+                None => info.remove_address(old),
+            };
+
+            let previous = core::mem::replace(&mut s.info, info);
+
+            // Goodbye for the records as they were on the old address.
+            if s.is_announced() {
+                let goodbye = LocalService {
+                    info: previous,
+                    state: ServiceState::Goodbye { sent: 0 },
+                    query: s.query,
+                    renamed: false,
+                    multicast: None,
+                };
+                if self.goodbyes.push(goodbye).is_err() {
+                    warn!("No room for goodbye from lost address: {}", old);
+                }
+            }
+
+            // Probe and look for remotes on the new network right away.
+            if new.is_some() {
+                s.restart_probing(now);
+                s.query = Backoff::new(now + jitter, &self.config);
+            } else if !s.is_announced() {
+                // The probe in progress counted the address that is gone.
+                s.restart_probing(now);
+            }
+        }
+
+        // Services without addresses can't be advertised anywhere.
+        self.services.retain(|s| !s.info.has_no_address());
+
+        let mut idx = 0;
+        while idx < self.query_targets.len() {
+            let qt = &self.query_targets[idx];
+
+            if qt.local_ip != lost {
+                idx += 1;
+                continue;
+            }
+
+            let moved = new.filter(|new| {
+                !self.query_targets.iter().any(|other| {
+                    other.local_ip == *new && other.host == qt.host && other.name == qt.name
+                })
+            });
+
+            match moved {
+                Some(new) => {
+                    let qt = &mut self.query_targets[idx];
+                    qt.local_ip = new;
+                    qt.backoff = Backoff::new(now + jitter, &self.config);
+                    idx += 1;
+                }
+                // Gone, or already queried for on the new address.
+                None => {
+                    self.query_targets.remove(idx);
+                }
+            }
+        }
+
+        self.cache.remove_network(lost.addr, lost.mask);
+
+        // Known answers of a query from the old address are no use anymore.
+        if self.known_answers.as_ref().is_some_and(|k| k.local == lost) {
+            self.known_answers = None;
+        }
+
+        self.update_local_ips();
+
+        true
+    }
+
+    /// Shut down the server.
+    ///
+    /// All announced services are withdrawn with goodbye packets, and the server stops
+    /// querying. Like for [`Server::remove_service`], the goodbye packets are produced
+    /// by the following calls to [`Server::handle`].
+    pub fn shutdown(&mut self) {
+        while !self.services.is_empty() {
+            let service = self.services.remove(0);
+            self.queue_goodbye(service);
+        }
+
+        self.query_targets.clear();
+
+        self.update_local_ips();
+    }
+
+    /// Withdraw a service that was removed from the services.
+    fn queue_goodbye(&mut self, service: LocalService<'a, LLEN, SUBLEN>) {
+        let Some(goodbye) = service.into_goodbye() else {
+            return;
+        };
+        if let Err(_e) = self.goodbyes.push(goodbye) {
+            warn!("No room for goodbye");
+        }
+    }
+
+    /// Collect the distinct local addresses of services and query targets.
+    ///
+    /// Withdrawn services are left out, they send their goodbyes from their own addresses.
+    fn update_local_ips(&mut self) {
+        self.local_ips.clear();
+
+        let from_services = self.services.iter().flat_map(|s| s.local_ips());
+
+        let from_queries = self.query_targets.iter().map(|qt| qt.local_ip);
+
+        for loc in from_services.chain(from_queries) {
+            if self.local_ips.contains(&loc) {
+                continue;
+            }
+            if self.local_ips.push(loc).is_err() {
+                warn!("Local address does not fit: {}", loc.addr);
+                continue;
+            }
+            self.rng.mix_ip(loc.addr);
+        }
+
+        // Carry on the rounds from the start if the list shrunk.
+        if self.next_advertise_idx >= self.local_ips.len() {
+            self.next_advertise_idx = 0;
+            self.advertise_sent = 0;
+        }
+        if self.next_query_idx >= self.local_ips.len() {
+            self.next_query_idx = 0;
+        }
+    }
+
+    fn poll_timeout(&self) -> Time {
+        if self.services.iter().any(|s| s.renamed) || !self.goodbyes.is_empty() {
+            return self.last_now;
+        }
+
+        if self.cache.has_events() || !self.uncached.is_empty() || self.known_answers.is_some() {
+            return self.last_now;
+        }
+
+        let mut next = self.next_query().unwrap_or(Time::from_millis(u64::MAX));
+
+        if let Some(expiry) = self.cache.next_expiry() {
+            next = next.min(expiry);
+        }
+
+        if let Some(response) = self.responses.iter().map(|r| r.at).min() {
+            next = next.min(response);
+        }
+
+        if let Some(refresh) = self.cache.next_refresh() {
+            next = next.min(refresh);
+        }
+
+        if self.services.iter().any(|s| s.is_announced()) {
+            next = next.min(self.next_advertise);
+        }
+
+        for s in self.services.iter() {
+            if let ServiceState::Probing { next: probe, .. } = s.state {
+                next = next.min(probe);
+            }
+        }
+
+        next
+    }
+
+    /// Handle some input and poll for the first output.
+    ///
+    /// The same as [`Server::handle_input`] followed by [`Server::poll_output`]. Only
+    /// [`Output::Timeout`] means there is nothing more to do right now. After any other
+    /// output, call again with [`Input::Timeout`] to get the rest.
+    pub fn handle(
+        &mut self,
+        input: Input<'_>,
+        buffer: &mut [u8],
+    ) -> Output<'static, LLEN, SLEN, SUBLEN> {
+        self.handle_input(input);
+        self.poll_output(buffer)
+    }
+
+    /// Handle some input.
+    ///
+    /// You can send [`Input::Timeout`] whenever. Follow up with [`Server::poll_output`]
+    /// to get what the input leads to.
+    pub fn handle_input(&mut self, input: Input<'_>) {
+        match input {
+            Input::Timeout(now) => {
+                self.last_now = now;
+                self.cache.expire(now);
+            }
+            Input::Packet(data, from) => self.handle_packet(data, from),
+        }
+    }
+
+    /// Poll for the next output.
+    ///
+    /// One input can lead to several outputs, such as responses on several interfaces
+    /// and many remote services. Call this until it returns [`Output::Timeout`], which
+    /// means there is nothing more to do right now. The `buffer` is for outgoing packets.
+    /// Upon [`Output::Packet`] the buffer will be filled to some point with data to transmit.
+    pub fn poll_output(&mut self, buffer: &mut [u8]) -> Output<'static, LLEN, SLEN, SUBLEN> {
+        let now = self.last_now;
+
+        if let Some(ret) = self.poll_renamed() {
+            return ret;
+        }
+
+        if let Some(ret) = self.poll_cache() {
+            return ret;
+        }
+
+        if !self.uncached.is_empty() {
+            return Output::Remote(self.uncached.remove(0));
+        }
+
+        if let Some(found) = self.service_types.get(self.service_types_reported) {
+            self.service_types_reported += 1;
+            return Output::ServiceType(found.clone());
+        }
+
+        if !self.resolved.is_empty() {
+            let (hostname, ip) = self.resolved.remove(0);
+            return Output::Resolved(hostname, ip);
+        }
+
+        if let Some(ret) = self.do_known_answers(buffer) {
+            return ret;
+        }
+
+        if let Some(ret) = self.do_goodbye(buffer) {
+            return ret;
+        }
+
+        if let Some(ret) = self.do_responses(now, buffer) {
+            return ret;
+        }
+
+        if let Some(ret) = self.do_probe(now, buffer) {
+            return ret;
+        }
+
+        if let Some(ret) = self.do_refresh(now, buffer) {
+            return ret;
+        }
+
+        let has_announced = self.services.iter().any(|s| s.is_announced());
+
+        if has_announced && now >= self.next_advertise {
+            let send_from = self.local_ips[self.next_advertise_idx];
+
+            let ret = self.do_advertise(buffer, send_from);
+
+            // The answers that didn't fit follow from the same local ip.
+            if self.advertise_sent == 0 {
+                self.next_advertise_idx += 1;
+            }
+
+            if self.next_advertise_idx == self.local_ips.len() {
+                self.next_advertise_idx = 0;
+                self.announcements = self.announcements.saturating_add(1);
+                self.next_advertise = now + self.advertise_interval();
+            }
+
+            match ret {
+                Some(ret) => ret,
+                // No announced services on this local ip, move on to the next.
+                None => self.poll_output(buffer),
+            }
+        } else if !self.local_ips.is_empty() && self.next_query().is_some_and(|t| now >= t) {
+            let send_from = self.local_ips[self.next_query_idx];
+
+            let ret = self.do_query(now, buffer, send_from);
+
+            self.next_query_idx += 1;
+
+            if self.next_query_idx == self.local_ips.len() {
+                self.next_query_idx = 0;
+                self.queries_sent(now);
+            }
+
+            match ret {
+                Some(ret) => ret,
+                // Nothing to query for on this local ip, move on to the next.
+                None => self.poll_output(buffer),
+            }
+        } else {
+            Output::Timeout(self.poll_timeout())
+        }
+    }
+
+    fn poll_renamed(&mut self) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let service = self.services.iter_mut().find(|s| s.renamed)?;
+        service.renamed = false;
+
+        let info = service.info.try_to_owned()?;
+
+        Some(Output::Renamed(info))
+    }
+
+    fn poll_cache(&mut self) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let ret = match self.cache.poll_event()? {
+            CacheEvent::Changed(info) => Output::Remote(info),
+            CacheEvent::Lost(info) => Output::RemoteLost(info),
+        };
+        Some(ret)
+    }
+
+    /// Announce the services from the start, i.e. after probing or a change.
+    fn restart_announcements(&mut self) {
+        self.next_advertise = self.last_now;
+        self.next_advertise_idx = 0;
+        self.advertise_sent = 0;
+        self.announcements = 0;
+    }
+
+    /// Time until the next advertise round. The announcements double the interval
+    /// each time, and are followed by regular advertisements.
+    fn advertise_interval(&self) -> u64 {
+        let config = &self.config;
+
+        if self.announcements < config.announce_count {
+            let doublings = (self.announcements - 1).min(32);
+            config.announce_interval.saturating_mul(1 << doublings)
+        } else {
+            config.advertise_interval
+        }
+    }
+
+    /// A random delay (RFC 6762 5.2, 6).
+    fn jitter(&mut self) -> u64 {
+        self.rng
+            .between(self.config.jitter_min, self.config.jitter_max)
+    }
+
+    fn probe_jitter(&mut self) -> u64 {
+        self.rng.between(0, self.config.probe_jitter)
+    }
+
+    fn truncated_delay(&mut self) -> u64 {
+        self.rng.between(TRUNCATED_DELAY_MIN, TRUNCATED_DELAY_MAX)
+    }
+
+    /// Backoff for a new service type, with the first query after a random delay.
+    fn first_query(&mut self) -> Backoff {
+        let at = self.last_now + self.jitter();
+        Backoff::new(at, &self.config)
+    }
+
+    /// The next time some service type is due for a query.
+    fn next_query(&self) -> Option<Time> {
+        let services = self.services.iter().map(|s| s.query.next);
+
+        let targets = self.query_targets.iter().map(|qt| qt.backoff.next);
+
+        services.chain(targets).min()
+    }
+
+    /// Back off the queries that were due, once sent from all local ips.
+    fn queries_sent(&mut self, now: Time) {
+        let config = &self.config;
+
+        let services = self.services.iter_mut().map(|s| &mut s.query);
+
+        let targets = self.query_targets.iter_mut().map(|qt| &mut qt.backoff);
+
+        for backoff in services.chain(targets) {
+            if backoff.is_due(now) {
+                backoff.sent(now, config);
+            }
+        }
+    }
+
+    fn next_txid(&mut self) -> u16 {
+        let x = self.next_txid;
+        self.next_txid = self.next_txid.wrapping_add(1);
+        x
+    }
+
+    fn do_probe(
+        &mut self,
+        now: Time,
+        buffer: &mut [u8],
+    ) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        // Services that sent all probes without hearing about a conflict are announced.
+        let mut probing_done = false;
+
+        for s in self.services.iter_mut() {
+            let done = matches!(
+                s.state,
+                ServiceState::Probing {
+                    sent: PROBE_COUNT,
+                    ..
+                }
+            );
+            if done && s.probe_due(now) {
+                debug!("Probing done: {:?}", s.info.instance_name());
+                s.state = ServiceState::Announced;
+                probing_done = true;
+            }
+        }
+
+        if probing_done {
+            self.restart_announcements();
+        }
+
+        let local = self.services.iter().find_map(|s| s.probe_from(now))?;
+
+        let id = self.next_txid();
+        self.txid_query = id;
+
+        let output = {
+            let mut request: Request<QLEN, ALEN, LLEN> = Request {
+                id,
+                flags: Flags::standard_request(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+
+            let to_probe = self
+                .services
+                .iter()
+                .filter(|s| s.probe_from(now) == Some(local));
+
+            for s in to_probe {
+                // The first probe asks for unicast responses (RFC 6762 8.1).
+                let first = matches!(s.state, ServiceState::Probing { sent: 0, .. });
+                let query = Query {
+                    name: s.info.instance_name(),
+                    qtype: QType::Any,
+                    qclass: QClass::IN,
+                    unicast_response: first,
+                };
+                let _ = request.queries.push(query);
+
+                for answer in s.probe_answers() {
+                    let _ = request.authorities.push(answer);
+                }
+            }
+
+            debug!("Send probe (from {}): {:?}", local.addr, request);
+
+            let mut buf = Writer::<LK>::new(buffer);
+            request.serialize(&mut buf);
+            packet_output(&buf, Cast::multi(local.addr))
+        };
+
+        for s in self.services.iter_mut() {
+            if s.probe_from(now) != Some(local) {
+                continue;
+            }
+            let addresses = s.local_ips().count();
+            if let ServiceState::Probing { sent, next, from } = &mut s.state {
+                *from += 1;
+                // Sent from all addresses, on to the next probe.
+                if *from >= addresses {
+                    *from = 0;
+                    *sent += 1;
+                    *next = now + PROBE_INTERVAL;
+                }
+            }
+        }
+
+        Some(output)
+    }
+
+    fn do_goodbye(&mut self, buffer: &mut [u8]) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let local = self.goodbyes.iter().find_map(|s| s.goodbye_from())?;
+
+        let output = {
+            let mut response: Response<QLEN, ALEN, LLEN> = Response {
+                id: 0,
+                flags: Flags::standard_response(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+
+            let to_consider = self
+                .goodbyes
+                .iter()
+                .filter(|s| s.goodbye_from() == Some(local));
+
+            for service in to_consider {
+                let answers = service.info.as_answers_from(true, local.addr);
+
+                for mut answer in answers {
+                    // Records still held by other services are valid, such as the address
+                    // record of a host with several services, or records kept by an update.
+                    let in_use = self.services.iter().any(|s| {
+                        s.is_on(local.addr)
+                            && s.info
+                                .as_answers_from(true, local.addr)
+                                .any(|a| a.name == answer.name && a.record == answer.record)
+                    });
+                    if in_use {
+                        continue;
+                    }
+                    answer.ttl = 0;
+                    if !response.answers.contains(&answer) {
+                        let _ = response.answers.push(answer);
+                    }
+                }
+            }
+
+            if response.answers.is_empty() {
+                None
+            } else {
+                debug!("Goodbye response (from {}): {:?}", local.addr, response);
+
+                let mut buf = Writer::<LK>::new(buffer);
+                response.serialize(&mut buf);
+                Some(packet_output(&buf, Cast::multi(local.addr)))
+            }
+        };
+
+        for s in self.goodbyes.iter_mut() {
+            if s.goodbye_from() == Some(local) {
+                if let ServiceState::Goodbye { sent } = &mut s.state {
+                    *sent += 1;
+                }
+            }
+        }
+
+        // Goodbyes are dropped once sent from all their addresses.
+        self.goodbyes.retain(|s| s.goodbye_from().is_some());
+
+        match output {
+            Some(output) => Some(output),
+            // Nothing to withdraw from this local ip, move on to the next.
+            None => self.do_goodbye(buffer),
+        }
+    }
+
+    /// Send the responses that are due, aggregated per destination (RFC 6762 6.3).
+    fn do_responses(
+        &mut self,
+        now: Time,
+        buffer: &mut [u8],
+    ) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let first = self.responses.iter().find(|r| now >= r.at)?;
+        let (from, to) = (first.from, first.to);
+
+        let is_same = move |r: &PendingResponse| now >= r.at && r.from == from && r.to == to;
+
+        let legacy = matches!(to, Destination::Legacy(..));
+
+        let output = {
+            // Responses have id 0 and no queries, except to legacy queriers (RFC 6762 6, 6.7).
+            let mut response: Response<QLEN, ALEN, LLEN> = Response {
+                id: match to {
+                    Destination::Legacy(_, id) => id,
+                    _ => 0,
+                },
+                flags: Flags::standard_response(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+
+            for pending in self.responses.iter().filter(|r| is_same(r)) {
+                // The service might be withdrawn or renamed since the query.
+                let service = self
+                    .services
+                    .iter()
+                    .find(|s| s.is_announced() && s.info.instance_name() == pending.instance_name);
+
+                let Some(service) = service else {
+                    continue;
+                };
+
+                let info = &service.info;
+
+                if legacy {
+                    let query = Query {
+                        name: pending.qname.label(),
+                        qtype: pending.qtype,
+                        qclass: pending.qclass,
+                        unicast_response: false,
+                    };
+                    if !response.queries.contains(&query) {
+                        let _ = response.queries.push(query);
+                    }
+                }
+
+                // Legacy queriers don't know about cache flush, and should not cache
+                // for long (RFC 6762 6.7).
+                let service_type = pending
+                    .records
+                    .has(Records::SERVICE_TYPE)
+                    .then(|| info.ptr_answer_for(&pending.qname.label()));
+
+                let answers = info
+                    .as_answers_from(!legacy, from)
+                    .filter(|a| pending.is_wanted(a))
+                    .chain(service_type);
+
+                for mut answer in answers {
+                    if legacy {
+                        answer.ttl = answer.ttl.min(LEGACY_TTL);
+                    }
+                    if !response.answers.contains(&answer) {
+                        let _ = response.answers.push(answer);
+                    }
+                }
+            }
+
+            if response.answers.is_empty() {
+                None
+            } else {
+                add_host_addresses(&self.services, &response.answers, &mut response.additionals);
+
+                if legacy {
+                    for additional in response.additionals.iter_mut() {
+                        additional.ttl = additional.ttl.min(LEGACY_TTL);
+                    }
+                }
+
+                debug!("Send response (from {}): {:?}", from, response);
+
+                let cast = match to {
+                    Destination::Multicast => Cast::multi(from),
+                    Destination::Unicast(target) | Destination::Legacy(target, _) => {
+                        Cast::Uni { from, target }
+                    }
+                };
+
+                let mut buf = Writer::<LK>::new(buffer);
+                response.serialize(&mut buf);
+                Some(packet_output(&buf, cast))
+            }
+        };
+
+        if to == Destination::Multicast && output.is_some() {
+            // The service type alone is not the records of the service.
+            let with_records = |r: &&PendingResponse| r.records != Records::SERVICE_TYPE;
+
+            for pending in self
+                .responses
+                .iter()
+                .filter(|r| is_same(r))
+                .filter(with_records)
+            {
+                let service = self
+                    .services
+                    .iter_mut()
+                    .find(|s| s.info.instance_name() == pending.instance_name);
+                if let Some(service) = service {
+                    service.multicast = Some(now);
+                }
+            }
+        }
+
+        self.responses.retain(|r| !is_same(r));
+
+        match output {
+            Some(output) => Some(output),
+            // Nothing left to respond with, move on to the next.
+            None => self.do_responses(now, buffer),
+        }
+    }
+
+    /// Queue a response, or add to one waiting for the same destination and query.
+    fn queue_response(&mut self, response: PendingResponse) {
+        let waiting = self.responses.iter_mut().find(|r| {
+            r.from == response.from
+                && r.querier == response.querier
+                && r.to == response.to
+                && r.qname == response.qname
+                && r.qtype == response.qtype
+                && r.instance_name == response.instance_name
+        });
+
+        if let Some(waiting) = waiting {
+            waiting.records = waiting.records.with(response.records);
+            return;
+        }
+
+        if self.responses.push(response).is_err() {
+            warn!("No room for response");
+        }
+    }
+
+    fn do_advertise(
+        &mut self,
+        buffer: &mut [u8],
+        local: LocalIp,
+    ) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let output = {
+            let mut response: Response<QLEN, ALEN, LLEN> = Response {
+                id: 0,
+                flags: Flags::standard_response(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+
+            let to_consider = self
+                .services
+                .iter()
+                .filter(|s| s.is_announced() && s.is_on(local.addr));
+
+            // Only the addresses valid on the interface we send from (RFC 6762 6.2).
+            let answers = to_consider.flat_map(|s| s.info.as_answers_from(true, local.addr));
+
+            let sent = self.advertise_sent;
+            let mut more = false;
+
+            for answer in answers.skip(sent) {
+                if response.answers.push(answer).is_err() {
+                    more = true;
+                    break;
+                }
+            }
+
+            if response.answers.is_empty() {
+                self.advertise_sent = 0;
+                return None;
+            }
+
+            self.advertise_sent = if more {
+                sent + response.answers.len()
+            } else {
+                0
+            };
+
+            add_host_addresses(&self.services, &response.answers, &mut response.additionals);
+
+            debug!("Advertise response (from {}): {:?}", local.addr, response);
+
+            let mut buf = Writer::<LK>::new(buffer);
+
+            response.serialize(&mut buf);
+
+            packet_output(&buf, Cast::multi(local.addr))
+        };
+
+        let now = self.last_now;
+        for s in self.services.iter_mut() {
+            if s.is_announced() && s.is_on(local.addr) {
+                s.multicast = Some(now);
+            }
+        }
+
+        Some(output)
+    }
+
+    /// Query for cached remote records close to expiry.
+    fn do_refresh(
+        &mut self,
+        now: Time,
+        buffer: &mut [u8],
+    ) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let mut request: Request<QLEN, ALEN, LLEN> = Request {
+            id: 0,
+            flags: Flags::standard_request(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+
+        let Some(first) = self.local_ips.first().copied() else {
+            // No local ip to send from, nothing to refresh.
+            while self
+                .cache
+                .poll_refresh::<QLEN>(now, &mut Vec::new())
+                .is_some()
+            {}
+            return None;
+        };
+
+        let remote = self.cache.poll_refresh(now, &mut request.queries)?;
+
+        let local = self
+            .local_ips
+            .iter()
+            .find(|l| is_same_network(l.addr, l.mask, remote))
+            .copied()
+            .unwrap_or(first);
+
+        // Not next_txid(), since the queries borrow the cache.
+        request.id = self.next_txid;
+        self.next_txid = request.id.wrapping_add(1);
+        self.txid_query = request.id;
+
+        debug!("Send refresh (from {}): {:?}", local.addr, request);
+
+        let mut buf = Writer::<LK>::new(buffer);
+        request.serialize(&mut buf);
+
+        Some(packet_output(&buf, Cast::multi(local.addr)))
+    }
+
+    fn do_query(
+        &mut self,
+        now: Time,
+        buffer: &mut [u8],
+        local: LocalIp,
+    ) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let mut queries: Vec<Query<'_, LLEN>, QLEN> = Vec::new();
+
+        let to_consider = self
+            .services
+            .iter()
+            .filter(|s| s.query.is_due(now) && s.is_on(local.addr));
+
+        // The first query asks for unicast responses, to not flood the network with
+        // responses at startup (RFC 6762 5.4).
+        for service in to_consider {
+            let query = Query {
+                name: service.info.service_type(),
+                qtype: QType::PTR,
+                qclass: QClass::IN,
+                unicast_response: service.query.first,
+            };
+            let _ = queries.push(query);
+        }
+
+        for qt in self.query_targets.iter() {
+            if qt.local_ip == local && qt.backoff.is_due(now) {
+                let qtypes: &[QType] = if qt.host {
+                    &[QType::A, QType::AAAA]
+                } else {
+                    &[QType::PTR]
+                };
+
+                for qtype in qtypes {
+                    let query = Query {
+                        name: qt.name.clone(),
+                        qtype: *qtype,
+                        qclass: QClass::IN,
+                        unicast_response: qt.backoff.first,
+                    };
+                    let _ = queries.push(query);
+                }
+            }
+        }
+
+        if queries.is_empty() {
+            return None;
+        }
+
+        // Known addresses would keep the hosts we resolve from answering, so known
+        // answers are only for the browsing.
+        let mut browse: Vec<Query<'_, LLEN>, QLEN> = Vec::new();
+        for query in queries.iter().filter(|q| q.qtype == QType::PTR) {
+            let _ = browse.push(query.clone());
+        }
+
+        // Not next_txid(), since the queries borrow the services.
+        let id = self.next_txid;
+        self.next_txid = id.wrapping_add(1);
+
+        let mut request: Request<QLEN, ALEN, LLEN> = Request {
+            id,
+            flags: Flags::standard_request(),
+            queries,
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+
+        self.txid_query = request.id;
+
+        let more = self
+            .cache
+            .known_answers(self.last_now, &browse, 0, &mut request.answers);
+
+        debug!("Send request (from {}): {:?}", local.addr, request);
+
+        let (output, more) =
+            serialize_truncated::<LK, _, _, _, _, _>(&mut request, more, buffer, local.addr);
+
+        if more {
+            // The rest of the known answers follow in the next packets.
+            // The names are copied, since the services can change until then.
+            let mut names = Vec::new();
+            for name in browse.iter().filter_map(|q| q.name.try_to_owned()) {
+                let _ = names.push(name);
+            }
+            self.known_answers = Some(KnownAnswers {
+                local,
+                names,
+                sent: request.answers.len(),
+            });
+        }
+
+        Some(output)
+    }
+
+    /// Send the known answers that didn't fit in the query (RFC 6762 7.2).
+    fn do_known_answers(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Option<Output<'static, LLEN, SLEN, SUBLEN>> {
+        let pending = self.known_answers.as_ref()?;
+
+        // Continuation packets have no queries, only known answers.
+        let mut request: Request<QLEN, ALEN, LLEN> = Request {
+            id: self.txid_query,
+            flags: Flags::standard_request(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+
+        let local = pending.local;
+        let sent = pending.sent;
+
+        let more = {
+            let mut queries: Vec<Query<'_, LLEN>, QLEN> = Vec::new();
+            for name in pending.names.iter() {
+                let _ = queries.push(Query {
+                    name: name.label(),
+                    qtype: QType::PTR,
+                    qclass: QClass::IN,
+                    unicast_response: false,
+                });
+            }
+
+            self.cache
+                .known_answers(self.last_now, &queries, sent, &mut request.answers)
+        };
+
+        if request.answers.is_empty() {
+            // The remaining answers expired since the query.
+            self.known_answers = None;
+            return None;
+        }
+
+        debug!("Send known answers (from {}): {:?}", local.addr, request);
+
+        let (output, more) =
+            serialize_truncated::<LK, _, _, _, _, _>(&mut request, more, buffer, local.addr);
+
+        let count = request.answers.len();
+
+        match &mut self.known_answers {
+            Some(pending) if more && count > 0 => pending.sent += count,
+            _ => self.known_answers = None,
+        }
+
+        Some(output)
+    }
+
+    fn handle_packet(&mut self, data: &[u8], from: SocketAddr) {
+        match Message::parse(data) {
+            Ok(Message::Request(request)) => self.handle_request(request, from),
+            Ok(Message::Response(response)) => self.handle_response(response, from),
+            Err(_e) => {
+                debug!("Ignore packet from {}: {:?}", from, _e);
+            }
+        }
+    }
+
+    fn handle_request(&mut self, request: Request<'_, QLEN, ALEN, LLEN>, from: SocketAddr) {
+        if request.queries.is_empty() {
+            if request.id != self.txid_query {
+                self.handle_known_answers(&request, from);
+            }
+            return;
+        }
+
+        // Probes are handled before the check for own requests, since another host
+        // might happen to use the same transaction id. Our own probes coming back
+        // have the same records as us, which is not a conflict.
+        if !request.authorities.is_empty() {
+            self.handle_probe(&request);
+        }
+
+        // Ignore requests from self
+        if request.id == self.txid_query {
+            return;
+        }
+
+        let Some(send_from) = self
+            .local_ips
+            .iter()
+            .find(|l| is_same_network(l.addr, l.mask, from.ip()))
+            .map(|l| l.addr)
+        else {
+            // Not on any of our networks.
+            return;
+        };
+
+        debug!("Incoming request: {:?} {:?}", from, request);
+
+        // Queriers not using the mDNS port are simple resolvers (RFC 6762 6.7).
+        let legacy = from.port() != MDNS_PORT;
+
+        let now = self.last_now;
+
+        // A truncated query is followed by more known answers, which we wait for
+        // before responding (RFC 6762 7.2).
+        let truncated = request.flags.is_truncated();
+
+        let delay_until = if truncated {
+            now + self.truncated_delay()
+        } else {
+            now + self.jitter()
+        };
+
+        let mut responses: Vec<PendingResponse, SLEN> = Vec::new();
+
+        for query in request.queries.iter() {
+            let to_consider = self
+                .services
+                .iter()
+                .filter(|s| s.is_announced() && s.is_on(from.ip()));
+
+            for service in to_consider {
+                let info = &service.info;
+                let mut records = Records::NONE;
+
+                // The PTR record asked for, of the type or of a subtype.
+                let ptr_name = info
+                    .ptr_names()
+                    .find(|name| query.qtype == QType::PTR && *name == query.name);
+
+                let is_ptr_query = ptr_name.is_some();
+
+                // Service type enumeration gets the type of every service (RFC 6763 9).
+                let is_enumeration = query.qtype == QType::PTR && query.name == SERVICE_TYPES;
+
+                // Host name resolution (RFC 6762 6).
+                let is_host_query = matches!(query.qtype, QType::Any | QType::A | QType::AAAA)
+                    && query.name == info.hostname();
+
+                let qname = match &ptr_name {
+                    Some(name) => name.clone(),
+                    None if is_enumeration => Label::new(SERVICE_TYPES),
+                    None if is_host_query => info.hostname(),
+                    None => info.instance_name(),
+                };
+
+                if is_enumeration
+                    && !is_known_answer(&info.ptr_answer_for(&qname), &request.answers)
+                {
+                    records = records.with(Records::SERVICE_TYPE);
+                }
+
+                if let Some(name) = &ptr_name {
+                    // The querier already knows about this instance.
+                    if is_known_answer(&info.ptr_answer_for(name), &request.answers) {
+                        continue;
+                    }
+
+                    // Only the PTR record asked for (RFC 6763 7.1).
+                    let answers = info
+                        .as_answers_from(true, send_from)
+                        .filter(|a| a.atype != QType::PTR || a.name == *name);
+
+                    for answer in answers {
+                        if !is_known_answer(&answer, &request.answers) {
+                            records = records.with(Records::of(answer.atype));
+                        }
+                    }
+                }
+
+                // Defend our instance name against probes from other hosts.
+                let is_instance_query = matches!(query.qtype, QType::Any | QType::SRV | QType::TXT);
+                if is_instance_query && query.name == info.instance_name() {
+                    for answer in [info.srv_answer(true), info.txt_answer(true)] {
+                        if !is_known_answer(&answer, &request.answers) {
+                            records = records.with(Records::of(answer.atype));
+                        }
+                    }
+                }
+
+                if is_host_query {
+                    let addresses = info.as_answers_from(true, send_from).filter(|a| {
+                        matches!(a.atype, QType::A | QType::AAAA)
+                            && (query.qtype == QType::Any || query.qtype == a.atype)
+                    });
+                    for answer in addresses {
+                        if !is_known_answer(&answer, &request.answers) {
+                            records = records.with(Records::of(answer.atype));
+                        }
+                    }
+                }
+
+                if records == Records::NONE {
+                    continue;
+                }
+
+                // A QU question is answered with unicast, unless the records have not been
+                // multicast for a while, in which case everyone gets them (RFC 6762 5.4).
+                let to = if legacy {
+                    Destination::Legacy(from, request.id)
+                } else if query.unicast_response && service.is_multicast_recently(now) {
+                    Destination::Unicast(from)
+                } else {
+                    Destination::Multicast
+                };
+
+                // Responses with shared records are delayed, since other hosts might answer
+                // the same query (RFC 6762 6).
+                let at = if truncated || (is_ptr_query || is_enumeration) && !legacy {
+                    delay_until
+                } else {
+                    now
+                };
+
+                let (Some(qname), Some(instance_name)) =
+                    (qname.try_to_owned(), info.instance_name().try_to_owned())
+                else {
+                    warn!("Name too long to respond: {:?}", qname);
+                    continue;
+                };
+
+                let response = PendingResponse {
+                    at,
+                    from: send_from,
+                    querier: from.ip(),
+                    to,
+                    qname,
+                    qtype: query.qtype,
+                    qclass: query.qclass,
+                    instance_name,
+                    records,
+                };
+
+                if responses.push(response).is_err() {
+                    warn!("No room for response: {:?}", info.instance_name());
+                }
+            }
+        }
+
+        while let Some(response) = responses.pop() {
+            self.queue_response(response);
+        }
+    }
+
+    /// More known answers following a truncated query (RFC 6762 7.2).
+    ///
+    /// Records the querier turns out to know are taken out of the responses waiting for
+    /// it. If this packet is truncated too, we wait a while longer.
+    fn handle_known_answers(&mut self, request: &Request<'_, QLEN, ALEN, LLEN>, from: SocketAddr) {
+        trace!("Known answers: {:?} {:?}", from, request);
+
+        let now = self.last_now;
+
+        let until = if request.flags.is_truncated() {
+            Some(now + self.truncated_delay())
+        } else {
+            None
+        };
+
+        let services = &self.services;
+
+        let waiting = self
+            .responses
+            .iter_mut()
+            .filter(|r| r.querier == from.ip() && r.at > now);
+
+        for pending in waiting {
+            if let Some(until) = until {
+                pending.at = pending.at.max(until);
+            }
+
+            let service = services
+                .iter()
+                .find(|s| s.info.instance_name() == pending.instance_name);
+
+            let Some(service) = service else {
+                continue;
+            };
+
+            let info = &service.info;
+
+            let ptr = info.ptr_answer_for(&pending.qname.label());
+            if pending.qtype == QType::PTR && is_known_answer(&ptr, &request.answers) {
+                pending.records = Records::NONE;
+                continue;
+            }
+
+            // The service type was checked above.
+            let mut records = if pending.records.has(Records::SERVICE_TYPE) {
+                Records::SERVICE_TYPE
+            } else {
+                Records::NONE
+            };
+
+            for answer in info.as_answers_from(true, pending.from) {
+                if pending.is_wanted(&answer) && !is_known_answer(&answer, &request.answers) {
+                    records = records.with(Records::of(answer.atype));
+                }
+            }
+
+            pending.records = records;
+        }
+
+        self.responses.retain(|r| r.records != Records::NONE);
+    }
+
+    /// Simultaneous probe tie-breaking (RFC 6762 8.2).
+    ///
+    /// If another host probes for the same name as us, the one with the lexicographically
+    /// later records wins. The loser waits a second and probes again, at which point the
+    /// winner should have announced and we get a proper conflict.
+    fn handle_probe(&mut self, request: &Request<'_, QLEN, ALEN, LLEN>) {
+        let now = self.last_now;
+
+        for s in self.services.iter_mut() {
+            if !matches!(s.state, ServiceState::Probing { .. }) {
+                continue;
+            }
+
+            let ordering = {
+                let name = s.info.instance_name();
+
+                let mut theirs: Vec<&Answer<'_, LLEN>, ALEN> = Vec::new();
+                for answer in request.authorities.iter().filter(|a| a.name == name) {
+                    let _ = theirs.push(answer);
+                }
+
+                if theirs.is_empty() {
+                    // Not probing for our name.
+                    continue;
+                }
+
+                let mut ours = s.probe_answers();
+
+                ours.sort_unstable_by(|a, b| a.cmp_probe(b));
+                theirs.sort_unstable_by(|a, b| a.cmp_probe(b));
+
+                cmp_probe_records(&ours, &theirs)
+            };
+
+            if ordering == Ordering::Less {
+                debug!("Lost probe tie-break: {:?}", s.info.instance_name());
+                s.restart_probing(now + PROBE_DEFER);
+            }
+        }
+    }
+
+    /// Check incoming records for conflicts with our instance names (RFC 6762 9).
+    fn detect_conflicts(&mut self, response: &Response<'_, QLEN, ALEN, LLEN>) {
+        let now = self.last_now;
+
+        for s in self.services.iter_mut() {
+            let conflict = {
+                let name = s.info.instance_name();
+                let [srv, txt] = s.probe_answers();
+
+                response.records().any(|a| {
+                    // TTL 0 is a goodbye, and not a conflict.
+                    if a.name != name || a.ttl == 0 {
+                        return false;
+                    }
+                    match &a.record {
+                        Record::SRV(_) => a.record != srv.record,
+                        Record::TXT(_) => a.record != txt.record,
+                        _ => false,
+                    }
+                })
+            };
+
+            if !conflict {
+                continue;
+            }
+
+            match s.state {
+                ServiceState::Probing { .. } => {
+                    // Each rename counts the suffix up, so a taken new name is
+                    // renamed again on the next conflict.
+                    let renamed = s.info.rename();
+                    if renamed {
+                        debug!("Name conflict, renamed to: {:?}", s.info.instance_name());
+                    } else {
+                        warn!("Name conflict, rename failed: {:?}", s.info.instance_name());
+                    }
+                    s.renamed = renamed;
+                }
+                ServiceState::Announced => {
+                    // Go back to probing, which will rename if the conflict persists.
+                    debug!("Name conflict, probing again: {:?}", s.info.instance_name());
+                }
+                // Goodbyes are kept apart, and never probe again.
+                ServiceState::Goodbye { .. } => continue,
+            }
+
+            s.restart_probing(now);
+        }
+    }
+
+    fn handle_response(&mut self, response: Response<'_, QLEN, ALEN, LLEN>, from: SocketAddr) {
+        let mut services = Vec::new();
+
+        trace!("Handle response: {:?} {:?}", from, response);
+
+        self.detect_conflicts(&response);
+
+        if self
+            .query_targets
+            .iter()
+            .any(|qt| !qt.host && qt.name == SERVICE_TYPES)
+        {
+            self.handle_service_types(&response);
+        }
+
+        if self.query_targets.iter().any(|qt| qt.host) {
+            self.handle_hosts(&response);
+        }
+
+        // There is at most one service per record.
+        ServiceInfo::from_answers::<ALEN>(response.records(), &mut services);
+
+        services.retain(|s| is_matching_service(s, &self.services, &self.query_targets));
+
+        let now = self.last_now;
+
+        // A goodbye only expires what is cached. A remote we didn't know about is
+        // leaving, and not to be reported as found (RFC 6762 10.1).
+        if response.records().all(|a| a.ttl == 0) {
+            services.clear();
+        }
+
+        // Services that don't fit in the cache are reported straight away, every time.
+        for service in services.iter() {
+            if self.cache.insert(service, from.ip(), now) {
+                continue;
+            }
+
+            warn!(
+                "Remote does not fit in cache: {:?}",
+                service.instance_name()
+            );
+
+            // Still waiting to be reported from an earlier packet.
+            if self.uncached.iter().any(|u| u == service) {
+                continue;
+            }
+
+            let queued = service
+                .try_to_owned()
+                .map(|owned| self.uncached.push(owned).is_ok())
+                .unwrap_or(false);
+
+            if !queued {
+                warn!("Remote dropped: {:?}", service.instance_name());
+            }
+        }
+
+        self.cache.refresh(response.records(), now);
+    }
+
+    /// Take the addresses for host names given to [`Server::resolve_host`]. The
+    /// resolved hosts are not queried for anymore.
+    fn handle_hosts(&mut self, response: &Response<'_, QLEN, ALEN, LLEN>) {
+        let before = self.query_targets.len();
+
+        let mut idx = 0;
+        while idx < self.query_targets.len() {
+            let qt = &self.query_targets[idx];
+            let mut found = false;
+
+            for answer in response.records() {
+                let ip = match &answer.record {
+                    Record::A(a) => IpAddr::V4(a.address),
+                    Record::AAAA(aaaa) => IpAddr::V6(aaaa.address),
+                    _ => continue,
+                };
+
+                // A goodbye is no address to use.
+                if !qt.host || answer.name != qt.name || answer.ttl == 0 {
+                    continue;
+                }
+
+                found = true;
+
+                // The same host might be resolved from several local addresses.
+                if self
+                    .resolved
+                    .iter()
+                    .any(|(h, i)| *i == ip && h == &answer.name)
+                {
+                    continue;
+                }
+
+                let Some(hostname) = qt.name.try_to_owned() else {
+                    warn!("Host name does not fit LLEN: {:?}", qt.name);
+                    continue;
+                };
+
+                if self.resolved.push((hostname, ip)).is_err() {
+                    warn!("No room for resolved address: {}", ip);
+                }
+            }
+
+            if found {
+                self.query_targets.remove(idx);
+            } else {
+                idx += 1;
+            }
+        }
+
+        if self.query_targets.len() != before {
+            self.update_local_ips();
+        }
+    }
+
+    /// Keep the service types in a response to [`Server::query_service_types`].
+    fn handle_service_types(&mut self, response: &Response<'_, QLEN, ALEN, LLEN>) {
+        for answer in response.records() {
+            let Record::PTR(ptr) = &answer.record else {
+                continue;
+            };
+
+            if answer.name != SERVICE_TYPES || self.service_types.iter().any(|t| *t == ptr.name) {
+                continue;
+            }
+
+            let Some(owned) = ptr.name.try_to_owned() else {
+                warn!("Service type does not fit LLEN: {:?}", ptr.name);
+                continue;
+            };
+
+            if self.service_types.push(owned).is_err() {
+                warn!("No room for service type: {:?}", ptr.name);
+            }
+        }
+    }
+}
+
+/// Serialize a request, leaving out known answers until it fits the buffer.
+///
+/// The TC bit is set if `more` known answers follow in another packet, or some are
+/// left out here (RFC 6762 7.2). Returns the output and whether known answers remain.
+fn serialize_truncated<
+    const LK: usize,
+    const QLEN: usize,
+    const ALEN: usize,
+    const LLEN: usize,
+    const SLEN: usize,
+    const SUBLEN: usize,
+>(
+    request: &mut Request<'_, QLEN, ALEN, LLEN>,
+    mut more: bool,
+    buffer: &mut [u8],
+    from: IpAddr,
+) -> (Output<'static, LLEN, SLEN, SUBLEN>, bool) {
+    loop {
+        request.flags.set_truncated(more);
+
+        let mut buf = Writer::<LK>::new(buffer);
+        request.serialize(&mut buf);
+
+        if !buf.is_overflow() || request.answers.is_empty() {
+            return (packet_output(&buf, Cast::multi(from)), more);
+        }
+
+        request.answers.pop();
+        more = true;
+    }
+}
+
+/// The output for a serialized packet, unless it didn't fit in the buffer.
+fn packet_output<const LK: usize, const LLEN: usize, const SLEN: usize, const SUBLEN: usize>(
+    buf: &Writer<'_, '_, LK>,
+    cast: Cast,
+) -> Output<'static, LLEN, SLEN, SUBLEN> {
+    if buf.is_overflow() {
+        warn!("Packet does not fit in buffer, needs: {}", buf.needed());
+        Output::Overflow(buf.needed())
+    } else {
+        Output::Packet(buf.len(), cast)
+    }
+}
+
+/// Add the addresses of the other family for the answered hostnames.
+///
+/// A response with an A record includes the AAAA records of the same host as
+/// additional records, and vice versa (RFC 6762 6.2). Addresses of the same family
+/// on other interfaces are left out.
+fn add_host_addresses<
+    's,
+    const LLEN: usize,
+    const SLEN: usize,
+    const SUBLEN: usize,
+    const ARLEN: usize,
+>(
+    services: &'s Vec<LocalService<'_, LLEN, SUBLEN>, SLEN>,
+    answers: &[Answer<'s, LLEN>],
+    additionals: &mut Vec<Answer<'s, LLEN>, ARLEN>,
+) {
+    for answer in answers {
+        let other = match answer.atype {
+            QType::A => QType::AAAA,
+            QType::AAAA => QType::A,
+            _ => continue,
+        };
+
+        let siblings = services
+            .iter()
+            .filter(|s| s.is_announced() && s.info.hostname() == answer.name);
+
+        for sibling in siblings {
+            for address in sibling.info.ip_answers(answer.cache_flush) {
+                if address.atype == other
+                    && !answers.contains(&address)
+                    && !additionals.contains(&address)
+                {
+                    let _ = additionals.push(address);
+                }
+            }
+        }
+    }
+}
+
+/// Whether the querier already holds the record with at least half the TTL (RFC 6762 7.1).
+fn is_known_answer<const LLEN: usize>(
+    answer: &Answer<'_, LLEN>,
+    known: &[Answer<'_, LLEN>],
+) -> bool {
+    known
+        .iter()
+        .any(|k| k.name == answer.name && k.record == answer.record && k.ttl >= answer.ttl / 2)
+}
+
+pub(crate) fn is_same_network(ip: IpAddr, netmask: IpAddr, other: IpAddr) -> bool {
+    match (ip, netmask, other) {
+        (IpAddr::V4(ip), IpAddr::V4(mask), IpAddr::V4(other)) => {
+            (u32::from(ip) & u32::from(mask)) == (u32::from(other) & u32::from(mask))
+        }
+        (IpAddr::V6(ip), IpAddr::V6(mask), IpAddr::V6(other)) => ip
+            .segments()
+            .iter()
+            .zip(mask.segments().iter())
+            .zip(other.segments().iter())
+            .all(|((&ip_seg, &mask_seg), &other_seg)| {
+                (ip_seg & mask_seg) == (other_seg & mask_seg)
+            }),
+        _ => false,
+    }
+}
+
+/// Compare two sorted lists of probe records. When one list is a prefix of the other,
+/// the longer list wins.
+fn cmp_probe_records<const LLEN: usize>(
+    ours: &[Answer<'_, LLEN>],
+    theirs: &[&Answer<'_, LLEN>],
+) -> Ordering {
+    for (a, b) in ours.iter().zip(theirs.iter()) {
+        let ordering = a.cmp_probe(b);
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    ours.len().cmp(&theirs.len())
+}
+
+fn is_matching_service<const LLEN: usize, const SLEN: usize, const SUBLEN: usize>(
+    s1: &ServiceInfo<'_, LLEN, SUBLEN>,
+    services: &Vec<LocalService<'_, LLEN, SUBLEN>, SLEN>,
+    query_targets: &Vec<QueryTarget<'_, LLEN>, SLEN>,
+) -> bool {
+    let mut handled_service = false;
+    let mut is_self = false;
+
+    for s2 in services.iter().map(|s| &s.info) {
+        handled_service |= s1.service_type() == s2.service_type();
+
+        is_self |= s1.instance_name() == s2.instance_name()
+            && s1
+                .ip_addresses()
+                .any(|ip| s2.ip_addresses().any(|ip2| ip == ip2))
+            && s1.port() == s2.port();
+    }
+
+    // Browsing for a subtype only finds the instances that have it.
+    for qt in query_targets.iter().filter(|qt| !qt.host) {
+        handled_service |= s1.ptr_names().any(|name| name == qt.name);
+    }
+
+    handled_service && !is_self
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Input<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        use crate::format::FormatSocketAddr;
+        match self {
+            Input::Timeout(instant) => {
+                defmt::write!(fmt, "Timeout({:?})", instant);
+            }
+            Input::Packet(data, addr) => {
+                defmt::write!(
+                    fmt,
+                    "Packet([..{} bytes], {:?})",
+                    data.len(),
+                    FormatSocketAddr(*addr)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Cast {
+    fn format(&self, fmt: defmt::Formatter) {
+        use crate::format::{FormatIpAddr, FormatSocketAddr};
+        match self {
+            Cast::Multi { from, target } => {
+                defmt::write!(
+                    fmt,
+                    "Multi {{ from:{:?}, target:{:?} }}",
+                    FormatIpAddr(*from),
+                    FormatSocketAddr(*target)
+                );
+            }
+            Cast::Uni { from, target } => {
+                defmt::write!(
+                    fmt,
+                    "Uni {{ from:{:?}, target:{:?} }}",
+                    FormatIpAddr(*from),
+                    FormatSocketAddr(*target)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "std", test))]
+mod test {
+    use core::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::dns::{A, AAAA, PTR};
+    use crate::Txt;
+
+    /// The default [`ServerConfig::probe_delay`], with the most
+    /// [`ServerConfig::probe_jitter`]. The first probe is due by then.
+    const PROBE_START: u64 = 3000 + 250;
+
+    /// Config without random delays, for exact timing.
+    fn no_jitter() -> ServerConfig {
+        ServerConfig {
+            probe_jitter: 0,
+            jitter_min: 0,
+            jitter_max: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_seed_differs_per_node() {
+        let first_query = |ip: [u8; 4]| {
+            let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+            server.query("_test._tcp.local", ip, [255, 255, 255, 0]);
+
+            let mut buf = [0u8; 2048];
+            match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+                Output::Timeout(t) => t,
+                other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+            }
+        };
+
+        assert_ne!(first_query([192, 168, 0, 1]), first_query([192, 168, 0, 2]));
+    }
+
+    #[test]
+    fn seed_spreads_first_probe_and_query() {
+        let first = |seed: u64| {
+            let config = ServerConfig {
+                seed,
+                ..Default::default()
+            };
+            let server: Server<4, 4, 4, 4, 10> =
+                Server::new_with_config([test_service()].into_iter(), config);
+
+            let s = &server.services[0];
+            let ServiceState::Probing { next, .. } = s.state else {
+                panic!("expected Probing");
+            };
+            (next, s.query.next)
+        };
+
+        let (probe, query) = first(1);
+        assert!(probe >= Time::from_millis(3000) && probe <= Time::from_millis(3250));
+        assert!(query >= Time::from_millis(5020) && query <= Time::from_millis(5120));
+
+        let (other_probe, other_query) = first(2);
+        assert_ne!(probe, other_probe);
+        assert_ne!(query, other_query);
+    }
+
+    #[test]
+    fn discovery_only_query_fires_after_jitter() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+
+        // The first query waits a random 20-120ms.
+        let now = match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        assert!(now >= Time::from_millis(20) && now <= Time::from_millis(120));
+
+        match server.handle(Input::Timeout(now), &mut buf) {
+            Output::Packet(n, Cast::Multi { from, .. }) => {
+                assert!(n > 0, "packet should have content");
+                assert_eq!(from, IpAddr::from([192, 168, 0, 1]));
+
+                // The first query asks for unicast responses.
+                let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                assert!(request.queries[0].unicast_response);
+            }
+            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+        }
+
+        let now = server.poll_timeout();
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert!(!request.queries[0].unicast_response);
+    }
+
+    #[test]
+    fn discovery_only_no_advertisement() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+
+        // The first query goes out after the jitter.
+        let now = match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        // Then a timeout (no advertisement, no more queries yet).
+        match server.handle(Input::Timeout(now + 1), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn empty_server_does_nothing() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+
+        let mut buf = [0u8; 2048];
+
+        // With no services and no query targets, the server should just return a timeout.
+        match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    fn test_service_port(port: u16) -> ServiceInfo<'static, 4> {
+        ServiceInfo::new(
+            "_test._tcp.local",
+            "martin_test",
+            "mini.local",
+            [192, 168, 0, 1],
+            [255, 255, 255, 0],
+            port,
+        )
+    }
+
+    fn test_service() -> ServiceInfo<'static, 4> {
+        test_service_port(1234)
+    }
+
+    /// The test service with the subtype `_gpu`.
+    fn gpu_service() -> ServiceInfo<'static, 4, 1> {
+        let mut info = ServiceInfo::new(
+            "_test._tcp.local",
+            "martin_test",
+            "mini.local",
+            [192, 168, 0, 1],
+            [255, 255, 255, 0],
+            1234,
+        );
+        assert!(info.add_subtype("_gpu"));
+        info
+    }
+
+    const REMOTE: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 2)), 5353);
+
+    fn expect_packet<const LLEN: usize, const SLEN: usize, const SUBLEN: usize>(
+        output: Output<'_, LLEN, SLEN, SUBLEN>,
+    ) -> usize {
+        match output {
+            Output::Packet(n, _) => n,
+            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn probe_before_advertise() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+
+        let mut buf = [0u8; 2048];
+
+        // Nothing happens until probing starts.
+        match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+            Output::Timeout(t) => {
+                assert!(t >= Time::from_millis(3000) && t <= Time::from_millis(PROBE_START))
+            }
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+
+        for i in 0..3 {
+            let now = Time::from_millis(PROBE_START + i * PROBE_INTERVAL);
+            let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+            let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert_eq!(request.queries.len(), 1);
+            assert_eq!(request.queries[0].qtype, QType::Any);
+            assert_eq!(request.queries[0].name, "martin_test._test._tcp.local");
+            assert_eq!(request.authorities.len(), 2);
+        }
+
+        // After the last probe, the service is announced.
+        let now = Time::from_millis(PROBE_START + 3 * PROBE_INTERVAL);
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert_eq!(response.answers.len(), 4);
+    }
+
+    #[test]
+    fn probe_from_every_address() {
+        let mut info = test_service();
+        assert!(info.add_address([10, 0, 0, 1], [255, 0, 0, 0]));
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([info].into_iter());
+
+        let mut buf = [0u8; 2048];
+
+        for i in 0..3 {
+            let now = Time::from_millis(PROBE_START + i * PROBE_INTERVAL);
+            let mut output = server.handle(Input::Timeout(now), &mut buf);
+
+            for addr in [[192, 168, 0, 1], [10, 0, 0, 1]] {
+                match output {
+                    Output::Packet(_, Cast::Multi { from, .. }) => {
+                        assert_eq!(from, IpAddr::from(addr));
+                    }
+                    other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+                }
+                output = server.poll_output(&mut buf);
+            }
+
+            match output {
+                Output::Timeout(t) => assert_eq!(t, now + PROBE_INTERVAL),
+                other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+            }
+        }
+    }
+
+    #[test]
+    fn small_buffer_overflows() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+        let mut small: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+
+        let mut buf = [0u8; 2048];
+        let mut small_buf = [0u8; 20];
+
+        let now = Time::from_millis(PROBE_START);
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        // The packet is dropped, and we're told how big the buffer must be.
+        match small.handle(Input::Timeout(now), &mut small_buf) {
+            Output::Overflow(needed) => assert_eq!(needed, n),
+            other => panic!("expected Overflow, got {:?}", OutputDebug(other)),
+        }
+
+        match small.poll_output(&mut small_buf) {
+            Output::Timeout(t) => assert_eq!(t, now + PROBE_INTERVAL),
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn conflict_while_probing_renames() {
+        // Another host answers for our instance name, with a different port.
+        let other = test_service_port(4321);
+        let mut packet = [0u8; 2048];
+        let packet = {
+            let mut response: Response<4, 4, 4> = Response {
+                id: 0,
+                flags: Flags::standard_response(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+            for answer in other.as_answers(false) {
+                response.answers.push(answer).unwrap();
+            }
+
+            let mut w = Writer::<10>::new(&mut packet);
+            response.serialize(&mut w);
+            w.into_inner()
+        };
+
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(PROBE_START)), &mut buf);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Renamed(info) => {
+                assert_eq!(info.instance_name(), "martin_test (2)._test._tcp.local");
+            }
+            other => panic!("expected Renamed, got {:?}", OutputDebug(other)),
+        }
+
+        // The same packet also told us about the other host.
+        match server.poll_output(&mut buf) {
+            Output::Remote(info) => assert_eq!(info.port(), 4321),
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+
+        // Probing starts over with the new name.
+        let now = Time::from_millis(PROBE_START + 1);
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert_eq!(request.queries[0].name, "martin_test (2)._test._tcp.local");
+    }
+
+    #[test]
+    fn second_conflict_renames_again() {
+        // Other hosts already hold both our name and the first rename.
+        let first = test_service_port(4321);
+        let mut second = test_service_port(4322);
+        assert!(second.rename());
+
+        let mut first_buf = [0u8; 2048];
+        let first_packet = response_packet(&first, 120, &mut first_buf);
+        let mut second_buf = [0u8; 2048];
+        let second_packet = response_packet(&second, 120, &mut second_buf);
+
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(PROBE_START)), &mut buf);
+
+        for (packet, expected) in [
+            (first_packet, "martin_test (2)._test._tcp.local"),
+            (second_packet, "martin_test (3)._test._tcp.local"),
+        ] {
+            match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+                Output::Renamed(info) => assert_eq!(info.instance_name(), expected),
+                other => panic!("expected Renamed, got {:?}", OutputDebug(other)),
+            }
+            // Drain the remote found in the same packet.
+            let _ = server.poll_output(&mut buf);
+        }
+
+        let now = Time::from_millis(PROBE_START + 1);
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert_eq!(request.queries[0].name, "martin_test (3)._test._tcp.local");
+    }
+
+    #[test]
+    fn lost_probe_tie_break_defers() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(PROBE_START)), &mut buf);
+
+        // Another host probes for the same name. Higher port is lexicographically later.
+        let other = test_service_port(4321);
+        let mut request: Request<4, 4, 4> = Request {
+            id: 4711,
+            flags: Flags::standard_request(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        request
+            .queries
+            .push(Query {
+                name: other.instance_name(),
+                qtype: QType::Any,
+                qclass: QClass::IN,
+                unicast_response: true,
+            })
+            .unwrap();
+        for answer in [other.srv_answer(false), other.txt_answer(false)] {
+            request.authorities.push(answer).unwrap();
+        }
+
+        let mut packet = [0u8; 2048];
+        let mut w = Writer::<10>::new(&mut packet);
+        request.serialize(&mut w);
+        let packet = w.into_inner();
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(t) => assert_eq!(t, Time::from_millis(PROBE_START + PROBE_DEFER)),
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn announcements_follow_config() {
+        let config = ServerConfig {
+            probe_delay: 100,
+            announce_count: 3,
+            announce_interval: 500,
+            advertise_interval: 10_000,
+            ..no_jitter()
+        };
+        let mut server: Server<4, 4, 4, 4, 10> =
+            Server::new_with_config([test_service()].into_iter(), config);
+
+        let mut buf = [0u8; 2048];
+        let mut now = Time::from_millis(0);
+        let mut announced = std::vec::Vec::new();
+
+        while now < Time::from_millis(20_000) {
+            match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(n, _) => {
+                    if let Ok(Message::Response(_)) = Message::<4, 4, 4>::parse(&buf[..n]) {
+                        announced.push(now);
+                    }
+                }
+                Output::Timeout(t) => now = t,
+                other => panic!("unexpected {:?}", OutputDebug(other)),
+            }
+        }
+
+        let expected = [850, 1350, 2350, 12_350].map(Time::from_millis);
+        assert_eq!(announced, expected);
+    }
+
+    #[test]
+    fn query_backoff_per_target() {
+        let mut server: Server<4, 4, 4, 4, 10> =
+            Server::new_with_config(std::iter::empty(), no_jitter());
+        server.query("_a._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let mut now = Time::from_millis(0);
+        let mut sent = std::vec::Vec::new();
+
+        while now < Time::from_millis(8000) {
+            if now >= Time::from_millis(2000) && server.query_targets.len() == 1 {
+                server.query("_b._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+            }
+
+            match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(n, _) => {
+                    let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                    let names: std::vec::Vec<_> =
+                        request.queries.iter().map(|q| q.name.to_string()).collect();
+                    sent.push((Time::from_millis(0).millis_until(now), names));
+                }
+                // Stop by at 2000 to register the second target.
+                Output::Timeout(t) if now < Time::from_millis(2000) => {
+                    now = t.min(Time::from_millis(2000))
+                }
+                Output::Timeout(t) => now = t,
+                other => panic!("unexpected {:?}", OutputDebug(other)),
+            }
+        }
+
+        let a = "_a._tcp.local";
+        let b = "_b._tcp.local";
+        let expected = [
+            (0, vec![a]),
+            (1000, vec![a]),
+            (2000, vec![b]),
+            (3000, vec![a, b]),
+            (5000, vec![b]),
+            (7000, vec![a]),
+        ];
+        let expected: std::vec::Vec<_> = expected
+            .into_iter()
+            .map(|(t, names)| (t, names.into_iter().map(String::from).collect()))
+            .collect();
+        assert_eq!(sent, expected);
+    }
+
+    /// Run a server with the test service until it's announced.
+    fn announced_server() -> Server<'static, 4, 4, 4, 4, 10> {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+
+        let mut buf = [0u8; 2048];
+        for i in 0..=3 {
+            let now = Time::from_millis(PROBE_START + i * PROBE_INTERVAL);
+            expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        }
+
+        server
+    }
+
+    #[test]
+    fn remove_service_sends_goodbye() {
+        let mut server = announced_server();
+
+        assert!(!server.remove_service("other._test._tcp.local"));
+        assert!(server.remove_service("martin_test._test._tcp.local"));
+
+        let mut buf = [0u8; 2048];
+        let now = Time::from_millis(4000);
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        {
+            let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert_eq!(response.answers.len(), 4);
+            assert!(response.answers.iter().all(|a| a.ttl == 0));
+        }
+
+        // The service is gone, and there's nothing more to send.
+        assert!(server.services.is_empty());
+        let now = Time::from_millis(20_000);
+        match server.handle(Input::Timeout(now), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn add_service_is_probed() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        assert!(server.local_ips.is_empty());
+
+        assert!(server.add_service(test_service()));
+        assert!(!server.add_service(test_service()));
+        assert_eq!(server.local_ips.len(), 1);
+
+        let mut buf = [0u8; 2048];
+        let n = expect_packet(server.handle(Input::Timeout(Time::from_millis(1000)), &mut buf));
+
+        let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert_eq!(request.queries[0].qtype, QType::Any);
+        assert_eq!(request.authorities.len(), 2);
+    }
+
+    #[test]
+    fn update_service_withdraws_stale_records() {
+        let mut server = announced_server();
+
+        assert!(!server.update_service("other._test._tcp.local", test_service_port(4321)));
+        assert!(server.update_service("martin_test._test._tcp.local", test_service_port(4321)));
+
+        let mut buf = [0u8; 2048];
+        let now = Time::from_millis(4000);
+
+        // Only the SRV with the old port is withdrawn.
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        {
+            let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert_eq!(response.answers.len(), 1);
+            assert_eq!(response.answers[0].ttl, 0);
+            let Record::SRV(srv) = &response.answers[0].record else {
+                panic!("expected SRV record");
+            };
+            assert_eq!(srv.port, 1234);
+        }
+
+        // The new port is announced without probing again.
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        let port = response.answers.iter().find_map(|a| match &a.record {
+            Record::SRV(srv) => Some(srv.port),
+            _ => None,
+        });
+        assert_eq!(port, Some(4321));
+        assert!(response.answers.iter().all(|a| a.ttl > 0));
+
+        assert_eq!(server.services.len(), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "alloc"))]
+    fn update_service_refused_without_room_for_goodbye() {
+        let mut server = announced_server();
+
+        // Each update queues a goodbye for the old port, until there's no more room.
+        for port in 0..4 {
+            assert!(server.update_service("martin_test._test._tcp.local", test_service_port(port)));
+        }
+        assert!(!server.update_service("martin_test._test._tcp.local", test_service_port(9)));
+        assert_eq!(server.services[0].info.port(), 3);
+    }
+
+    #[test]
+    fn change_address_moves_to_new_network() {
+        let mut server = announced_server();
+
+        let remote = ServiceInfo::<4>::new(
+            "_test._tcp.local",
+            "other",
+            "other.local",
+            [192, 168, 0, 2],
+            [255, 255, 255, 0],
+            4321,
+        );
+
+        let mut buf = [0u8; 2048];
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&remote, 120, &mut packet);
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => assert_eq!(info.port(), 4321),
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+
+        assert!(!server.change_address([192, 168, 0, 9], [10, 0, 0, 1], [255, 0, 0, 0]));
+        assert!(server.change_address([192, 168, 0, 1], [10, 0, 0, 1], [255, 0, 0, 0]));
+
+        let old = IpAddr::from([192, 168, 0, 1]);
+        let new = IpAddr::from([10, 0, 0, 1]);
+        assert_eq!(server.local_ips.len(), 1);
+        assert_eq!(server.local_ips[0].addr, new);
+
+        let now = Time::from_millis(4000);
+
+        match server.handle(Input::Timeout(now), &mut buf) {
+            Output::RemoteLost(info) => assert_eq!(info.port(), 4321),
+            other => panic!("expected RemoteLost, got {:?}", OutputDebug(other)),
+        }
+
+        match server.handle(Input::Timeout(now), &mut buf) {
+            Output::Packet(n, Cast::Multi { from, .. }) => {
+                assert_eq!(from, old);
+                let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                assert_eq!(response.answers.len(), 4);
+                assert!(response.answers.iter().all(|a| a.ttl == 0));
+            }
+            other => panic!("expected goodbye, got {:?}", OutputDebug(other)),
+        }
+
+        match server.handle(Input::Timeout(now), &mut buf) {
+            Output::Packet(n, Cast::Multi { from, .. }) => {
+                assert_eq!(from, new);
+                let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                assert_eq!(request.authorities.len(), 2);
+            }
+            other => panic!("expected probe, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn remove_address_withdraws_service() {
+        let mut server = announced_server();
+
+        assert!(server.remove_address([192, 168, 0, 1]));
+        assert!(server.local_ips.is_empty());
+
+        let mut buf = [0u8; 2048];
+        let n = expect_packet(server.handle(Input::Timeout(Time::from_millis(4000)), &mut buf));
+        let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert!(response.answers.iter().all(|a| a.ttl == 0));
+
+        assert!(server.services.is_empty());
+    }
+
+    #[test]
+    fn remove_address_while_probing() {
+        let mut info = test_service();
+        assert!(info.add_address([10, 0, 0, 1], [255, 0, 0, 0]));
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([info].into_iter());
+
+        let mut buf = [0u8; 2048];
+
+        // The first probe went out from the first address only.
+        let now = Time::from_millis(PROBE_START);
+        expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        assert!(server.remove_address([10, 0, 0, 1]));
+
+        // Probing starts over from the address that is left.
+        for i in 0..3 {
+            let now = Time::from_millis(PROBE_START + i * PROBE_INTERVAL);
+            match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(_, Cast::Multi { from, .. }) => {
+                    assert_eq!(from, IpAddr::from([192, 168, 0, 1]));
+                }
+                other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+            }
+            match server.poll_output(&mut buf) {
+                Output::Timeout(t) => assert_eq!(t, now + PROBE_INTERVAL),
+                other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+            }
+        }
+
+        let now = Time::from_millis(PROBE_START + 3 * PROBE_INTERVAL);
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert!(response.answers.iter().all(|a| a.ttl > 0));
+        assert!(server.services[0].is_announced());
+    }
+
+    #[test]
+    fn add_address_probes_again() {
+        let mut server = announced_server();
+
+        assert!(server.add_address([10, 0, 0, 1], [255, 0, 0, 0]));
+        assert!(!server.add_address([10, 0, 0, 1], [255, 0, 0, 0]));
+        assert_eq!(server.local_ips.len(), 2);
+
+        let mut buf = [0u8; 2048];
+        let now = Time::from_millis(4000);
+
+        for addr in [[192, 168, 0, 1], [10, 0, 0, 1]] {
+            match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(n, Cast::Multi { from, .. }) => {
+                    assert_eq!(from, IpAddr::from(addr));
+                    let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                    assert_eq!(request.authorities.len(), 2);
+                }
+                other => panic!("expected probe, got {:?}", OutputDebug(other)),
+            }
+        }
+    }
+
+    #[test]
+    fn remove_probing_service_has_no_goodbye() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([test_service()].into_iter());
+
+        assert!(server.remove_service("martin_test._test._tcp.local"));
+        assert!(server.services.is_empty());
+    }
+
+    #[test]
+    fn shutdown_sends_goodbye_and_stops_querying() {
+        let mut server = announced_server();
+        server.query("_other._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        server.shutdown();
+
+        let mut buf = [0u8; 2048];
+        let now = Time::from_millis(4000);
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        {
+            let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert!(response.answers.iter().all(|a| a.ttl == 0));
+        }
+
+        match server.handle(Input::Timeout(now), &mut buf) {
+            Output::Timeout(t) => assert_eq!(t, Time::from_millis(u64::MAX)),
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    /// Serialize a response with all the answers for `info`, with the given TTL.
+    fn response_packet<'b, const SUBLEN: usize>(
+        info: &ServiceInfo<'_, 4, SUBLEN>,
+        ttl: u32,
+        packet: &'b mut [u8],
+    ) -> &'b [u8] {
+        let mut response: Response<4, 8, 4> = Response {
+            id: 0,
+            flags: Flags::standard_response(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        for mut answer in info.as_answers(true) {
+            answer.ttl = ttl;
+            response.answers.push(answer).unwrap();
+        }
+
+        let mut w = Writer::<10>::new(packet);
+        response.serialize(&mut w);
+        w.into_inner()
+    }
+
+    #[test]
+    fn remote_reported_once_and_expires() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service(), 120, &mut packet);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => assert_eq!(info.port(), 1234),
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+
+        // The same answers again are not reported, but the expiry is pushed forward.
+        let _ = server.handle(Input::Timeout(Time::from_millis(10_000)), &mut buf);
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+
+        // A changed remote is reported again.
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service_port(4321), 120, &mut packet);
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => assert_eq!(info.port(), 4321),
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+
+        let now = Time::from_millis(10_000 + 120_000);
+        loop {
+            match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(_, _) => continue,
+                Output::RemoteLost(info) => {
+                    assert_eq!(info.instance_name(), test_service().instance_name());
+                    break;
+                }
+                other => panic!("expected RemoteLost, got {:?}", OutputDebug(other)),
+            }
+        }
+    }
+
+    #[test]
+    fn goodbye_from_unknown_remote_is_not_reported() {
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service(), 0, &mut packet);
+
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+
+        // Nor is it lost, since it never was found.
+        let now = Time::from_millis(2000);
+        loop {
+            match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(_, _) => continue,
+                Output::Timeout(_) => break,
+                other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+            }
+        }
+    }
+
+    #[test]
+    fn every_remote_in_response_is_reported() {
+        let mut server: Server<4, 24, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        const NAMES: [&str; 6] = [
+            "CH1@in-a", "CH2@in-a", "CH1@in-b", "CH2@in-b", "CH1@in-c", "CH2@in-c",
+        ];
+
+        let remotes = NAMES.map(|name| {
+            ServiceInfo::<4>::new(
+                "_test._tcp.local",
+                name,
+                "audio.local",
+                [192, 168, 0, 2],
+                [255, 255, 255, 0],
+                1234,
+            )
+        });
+
+        let mut response: Response<4, 24, 4> = Response {
+            id: 0,
+            flags: Flags::standard_response(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        for remote in remotes.iter() {
+            for answer in remote.as_answers(true) {
+                response.answers.push(answer).unwrap();
+            }
+        }
+
+        let mut packet = [0u8; 2048];
+        let mut w = Writer::<10>::new(&mut packet);
+        response.serialize(&mut w);
+        let packet = w.into_inner();
+
+        let mut found = std::vec::Vec::new();
+
+        let mut input = Input::Packet(packet, REMOTE);
+        loop {
+            match server.handle(input, &mut buf) {
+                Output::Remote(info) => found.push(info.instance_name().to_string()),
+                Output::Timeout(_) => break,
+                other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+            }
+            input = Input::Timeout(Time::from_millis(0));
+        }
+
+        let expected: std::vec::Vec<_> = remotes
+            .iter()
+            .map(|r| r.instance_name().to_string())
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn dual_stack_advertises_both_addresses() {
+        let v6: Ipv6Addr = "fe80::1".parse().unwrap();
+        let v6_mask: Ipv6Addr = "ffff:ffff:ffff:ffff::".parse().unwrap();
+
+        let info_v6 = ServiceInfo::new(
+            "_test._tcp.local",
+            "martin_test",
+            "mini.local",
+            v6,
+            v6_mask,
+            1234,
+        );
+
+        // Both are announced at the same time.
+        let mut server: Server<4, 4, 4, 4, 10> =
+            Server::new_with_config([test_service(), info_v6].into_iter(), no_jitter());
+
+        let mut buf = [0u8; 2048];
+        let mut now = Time::from_millis(0);
+
+        for _ in 0..50 {
+            let (n, target) = match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(n, Cast::Multi { from, target }) if from == IpAddr::V6(v6) => {
+                    (n, target)
+                }
+                Output::Packet(_, _) => continue,
+                Output::Timeout(t) => {
+                    now = t;
+                    continue;
+                }
+                other => panic!("unexpected {:?}", OutputDebug(other)),
+            };
+
+            assert_eq!(target, SocketAddr::V6(GROUP_SOCK_V6));
+
+            let Ok(Message::Response(response)) = Message::<4, 4, 4>::parse(&buf[..n]) else {
+                // Probe
+                continue;
+            };
+
+            assert!(response.answers.iter().any(|a| a.atype == QType::AAAA));
+            assert_eq!(response.additionals.len(), 1);
+            let Record::A(a) = &response.additionals[0].record else {
+                panic!("expected A record");
+            };
+            assert_eq!(a.address, Ipv4Addr::new(192, 168, 0, 1));
+            return;
+        }
+
+        panic!("no advertisement from the IPv6 address");
+    }
+
+    #[test]
+    fn multi_address_advertises_per_interface() {
+        let v6: Ipv6Addr = "fe80::1".parse().unwrap();
+        let v6_mask: Ipv6Addr = "ffff:ffff:ffff:ffff::".parse().unwrap();
+
+        let mut info = test_service();
+        assert!(info.add_address([10, 0, 0, 1], [255, 0, 0, 0]));
+        assert!(info.add_address(v6, v6_mask));
+        assert!(!info.add_address([10, 0, 0, 1], [255, 0, 0, 0]));
+
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([info].into_iter());
+        assert_eq!(server.local_ips.len(), 3);
+
+        let mut buf = [0u8; 2048];
+        let mut now = Time::from_millis(0);
+
+        for _ in 0..50 {
+            let n = match server.handle(Input::Timeout(now), &mut buf) {
+                Output::Packet(n, Cast::Multi { from, .. })
+                    if from == IpAddr::from([10, 0, 0, 1]) =>
+                {
+                    n
+                }
+                Output::Packet(_, _) => continue,
+                Output::Timeout(t) => {
+                    now = t;
+                    continue;
+                }
+                other => panic!("unexpected {:?}", OutputDebug(other)),
+            };
+
+            let Ok(Message::Response(response)) = Message::<4, 4, 4>::parse(&buf[..n]) else {
+                // Probe
+                continue;
+            };
+
+            // Only the address of the interface, not 192.168.0.1.
+            let addresses: std::vec::Vec<_> = response
+                .answers
+                .iter()
+                .filter_map(|a| match &a.record {
+                    Record::A(a) => Some(a.address),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(addresses, [Ipv4Addr::new(10, 0, 0, 1)]);
+
+            assert_eq!(response.additionals.len(), 1);
+            let Record::AAAA(aaaa) = &response.additionals[0].record else {
+                panic!("expected AAAA record");
+            };
+            assert_eq!(aaaa.address, v6);
+            return;
+        }
+
+        panic!("no advertisement from 10.0.0.1");
+    }
+
+    #[test]
+    fn advertisement_beyond_alen_is_split() {
+        let mut info = test_service();
+        assert!(info.add_address([192, 168, 0, 5], [255, 255, 255, 0]));
+
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new([info].into_iter());
+
+        // Probes from both addresses.
+        let mut buf = [0u8; 2048];
+        for i in 0..3 {
+            let now = Time::from_millis(PROBE_START + i * PROBE_INTERVAL);
+            expect_packet(server.handle(Input::Timeout(now), &mut buf));
+            expect_packet(server.poll_output(&mut buf));
+        }
+
+        // Five answers don't fit ALEN without alloc, the rest follow in another packet.
+        let now = Time::from_millis(PROBE_START + 3 * PROBE_INTERVAL);
+        let mut output = server.handle(Input::Timeout(now), &mut buf);
+        let mut answers = std::vec::Vec::new();
+
+        loop {
+            match output {
+                Output::Packet(n, Cast::Multi { from, .. }) => {
+                    let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                    if from == IpAddr::from([192, 168, 0, 1]) {
+                        answers.extend(response.answers.iter().map(|a| a.atype));
+                    }
+                }
+                Output::Timeout(_) => break,
+                other => panic!("unexpected {:?}", OutputDebug(other)),
+            }
+            output = server.poll_output(&mut buf);
+        }
+
+        answers.sort_by_key(|t| t.to_u16());
+        assert_eq!(
+            answers,
+            [QType::A, QType::A, QType::PTR, QType::TXT, QType::SRV]
+        );
+    }
+
+    #[test]
+    fn remote_with_several_addresses() {
+        let mut server: Server<4, 8, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        let v6: Ipv6Addr = "fe80::2".parse().unwrap();
+        let mut remote = test_service();
+        remote.add_address(v6, Ipv6Addr::UNSPECIFIED);
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&remote, 120, &mut packet);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => {
+                let addresses: std::vec::Vec<_> = info.ip_addresses().collect();
+                assert_eq!(addresses, [IpAddr::from([192, 168, 0, 1]), IpAddr::V6(v6)]);
+            }
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn remote_from_additional_records() {
+        // Like many responders, only the PTR is an answer.
+        let remote = test_service();
+        let mut packet = [0u8; 2048];
+        let packet = {
+            let mut response: Response<4, 4, 4> = Response {
+                id: 0,
+                flags: Flags::standard_response(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+            for answer in remote.as_answers(true) {
+                if answer.atype == QType::PTR {
+                    response.answers.push(answer).unwrap();
+                } else {
+                    response.additionals.push(answer).unwrap();
+                }
+            }
+
+            let mut w = Writer::<10>::new(&mut packet);
+            response.serialize(&mut w);
+            w.into_inner()
+        };
+
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => assert_eq!(info.instance_name(), remote.instance_name()),
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn remote_carries_txt_entries() {
+        let mut txt = Txt::new();
+        txt.insert("version", Some(b"1.2"));
+        txt.insert("secure", None);
+        let remote = test_service().with_txt(txt.clone());
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&remote, 120, &mut packet);
+
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => assert_eq!(info.txt(), &txt),
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn remote_refreshed_before_expiry() {
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service(), 100, &mut packet);
+        let _ = server.handle(Input::Packet(packet, REMOTE), &mut buf);
+
+        for percent in [80, 85, 90, 95] {
+            let now = Time::from_millis(percent * 1000);
+            assert_eq!(server.cache.next_refresh(), Some(now));
+
+            let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+            let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+
+            let qtypes: std::vec::Vec<_> = request.queries.iter().map(|q| q.qtype).collect();
+            assert_eq!(qtypes, [QType::PTR, QType::SRV, QType::A]);
+        }
+
+        // An answer to the refresh resets the TTL, and there are no more refreshes.
+        let now = Time::from_millis(96_000);
+        let _ = server.handle(Input::Timeout(now), &mut buf);
+        let _ = server.handle(Input::Packet(packet, REMOTE), &mut buf);
+        assert_eq!(
+            server.cache.next_refresh(),
+            Some(Time::from_millis(176_000))
+        );
+    }
+
+    #[test]
+    fn query_includes_known_answers() {
+        let mut server: Server<4, 4, 4, 4, 10> =
+            Server::new_with_config(std::iter::empty(), no_jitter());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service(), 4500, &mut packet);
+        let _ = server.handle(Input::Packet(packet, REMOTE), &mut buf);
+
+        let now = server.poll_timeout();
+        assert_eq!(
+            now,
+            Time::from_millis(ServerConfig::default().query_interval)
+        );
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        // The PTR record is known, with the remaining TTL.
+        let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+        assert_eq!(request.queries[0].qtype, QType::PTR);
+        assert_eq!(request.answers.len(), 1);
+        assert_eq!(request.answers[0].atype, QType::PTR);
+        assert_eq!(request.answers[0].ttl, 4500 - 1);
+    }
+
+    #[test]
+    fn known_answers_continue_in_truncated_packets() {
+        let mut server: Server<4, 4, 4, 4, 10> =
+            Server::new_with_config(std::iter::empty(), no_jitter());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        for name in ["a", "b", "c"] {
+            let info = ServiceInfo::<4>::new(
+                "_test._tcp.local",
+                name,
+                "other.local",
+                [192, 168, 0, 2],
+                [255, 255, 255, 0],
+                1234,
+            );
+            let mut packet = [0u8; 2048];
+            let packet = response_packet(&info, 4500, &mut packet);
+            let _ = server.handle(Input::Packet(packet, REMOTE), &mut buf);
+            while !matches!(server.poll_output(&mut buf), Output::Timeout(_)) {}
+        }
+
+        // Room for the query and one known answer.
+        let mut buf = [0u8; 100];
+        let now = server.poll_timeout();
+        let n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+
+        {
+            let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert_eq!(request.queries.len(), 1);
+            assert_eq!(request.answers.len(), 1);
+            assert!(request.flags.is_truncated());
+        }
+
+        // The rest follow straight away, without queries.
+        let mut known = 1;
+        loop {
+            let n = expect_packet(server.poll_output(&mut buf));
+            let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert!(request.queries.is_empty());
+            known += request.answers.len();
+            if !request.flags.is_truncated() {
+                break;
+            }
+        }
+        assert_eq!(known, 3);
+
+        assert!(matches!(server.poll_output(&mut buf), Output::Timeout(_)));
+    }
+
+    #[test]
+    fn truncated_query_waits_for_known_answers() {
+        let mut server = announced_server();
+
+        let mut buf = [0u8; 2048];
+        let start = Time::from_millis(4000);
+        let _ = server.handle(Input::Timeout(start), &mut buf);
+
+        let mut request: Request<4, 4, 4> = Request {
+            id: 4711,
+            flags: Flags::standard_request(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        request.flags.set_truncated(true);
+        request
+            .queries
+            .push(Query {
+                name: Label::new("_test._tcp.local"),
+                qtype: QType::PTR,
+                qclass: QClass::IN,
+                unicast_response: false,
+            })
+            .unwrap();
+
+        let mut packet = [0u8; 2048];
+        let mut w = Writer::<10>::new(&mut packet);
+        request.serialize(&mut w);
+        let packet = w.into_inner();
+
+        let at = match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        assert!(at >= start + 400 && at <= start + 500);
+
+        // The next packet from the querier knows about us.
+        let service = test_service();
+        let mut request: Request<4, 4, 4> = Request {
+            id: 4711,
+            flags: Flags::standard_request(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        request.answers.push(service.ptr_answer()).unwrap();
+
+        let mut packet = [0u8; 2048];
+        let mut w = Writer::<10>::new(&mut packet);
+        request.serialize(&mut w);
+        let packet = w.into_inner();
+
+        let _ = server.handle(Input::Packet(packet, REMOTE), &mut buf);
+        assert!(server.responses.is_empty());
+
+        match server.handle(Input::Timeout(at), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    /// A PTR query for the test service type.
+    fn ptr_query_packet(unicast_response: bool, packet: &mut [u8]) -> &[u8] {
+        let mut request: Request<4, 4, 4> = Request {
+            id: 4711,
+            flags: Flags::standard_request(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        request
+            .queries
+            .push(Query {
+                name: Label::new("_test._tcp.local"),
+                qtype: QType::PTR,
+                qclass: QClass::IN,
+                unicast_response,
+            })
+            .unwrap();
+
+        let mut w = Writer::<10>::new(packet);
+        request.serialize(&mut w);
+        w.into_inner()
+    }
+
+    #[test]
+    fn multicast_response_is_delayed() {
+        let mut server = announced_server();
+
+        let mut buf = [0u8; 2048];
+        let mut packet = [0u8; 2048];
+        let packet = ptr_query_packet(false, &mut packet);
+
+        let start = Time::from_millis(4000);
+        let _ = server.handle(Input::Timeout(start), &mut buf);
+
+        let at = match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        assert!(at >= start + 20 && at <= start + 120);
+
+        // The same query again is answered by the response already waiting.
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(t) => assert_eq!(t, at),
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+        assert_eq!(server.responses.len(), 1);
+
+        match server.handle(Input::Timeout(at), &mut buf) {
+            Output::Packet(n, Cast::Multi { .. }) => {
+                let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                assert_eq!(response.id, 0);
+                assert_eq!(response.answers.len(), 4);
+            }
+            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+        }
+
+        assert!(server.responses.is_empty());
+    }
+
+    #[test]
+    fn upper_case_query_is_answered() {
+        let mut server = announced_server();
+
+        let mut buf = [0u8; 2048];
+        let mut packet = [0u8; 2048];
+        let mut request: Request<4, 4, 4> = Request {
+            id: 4711,
+            flags: Flags::standard_request(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
         };
+        request
+            .queries
+            .push(Query {
+                name: Label::new("_TEST._Tcp.LOCAL"),
+                qtype: QType::PTR,
+                qclass: QClass::IN,
+                unicast_response: false,
+            })
+            .unwrap();
+        let mut w = Writer::<10>::new(&mut packet);
+        request.serialize(&mut w);
+        let packet = w.into_inner();
+
+        let start = Time::from_millis(4000);
+        let _ = server.handle(Input::Timeout(start), &mut buf);
+
+        let at = match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        assert_eq!(server.responses.len(), 1);
+
+        match server.handle(Input::Timeout(at), &mut buf) {
+            Output::Packet(n, Cast::Multi { .. }) => {
+                let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                assert_eq!(response.answers.len(), 4);
+            }
+            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+        }
+    }
 
-        debug!("Send response: {:?}", response);
-        let mut buf = Writer::<LK>::new(buffer);
-        response.serialize(&mut buf);
+    #[test]
+    fn subtype_is_advertised_and_answered() {
+        let mut server: Server<4, 4, 4, 4, 10, 8, 1> = Server::new([gpu_service()].into_iter());
 
-        let send_from = self
-            .local_ips
-            .iter()
-            .find(|l| is_same_network(l.addr, l.mask, from.ip()))
-            // unwrap: is ok because above answers.is_empty() check means we must have had
-            // a match between incoming query and service records.
-            .unwrap()
-            .addr;
-
-        let cast = match qclass {
-            QClass::IN => Cast::Uni {
-                from: send_from,
-                target: from,
-            },
-            _ => Cast::Multi { from: send_from },
+        let is_subtype_ptr = |answer: &Answer<'_, 4>| {
+            answer.atype == QType::PTR && answer.name == "_gpu._sub._test._tcp.local"
         };
 
-        Output::Packet(buf.len(), cast)
-    }
+        let mut buf = [0u8; 2048];
+        let mut n = 0;
+        for i in 0..=3 {
+            let now = Time::from_millis(PROBE_START + i * PROBE_INTERVAL);
+            n = expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        }
 
-    fn handle_response<'x>(
-        &mut self,
-        response: Response<'x, QLEN, ALEN, LLEN>,
-        _from: SocketAddr,
-        _buffer: &mut [u8],
-    ) -> Output<'x, LLEN, SLEN> {
-        let mut services = Vec::new();
+        // The announcement has the PTR record of the subtype too.
+        {
+            let response = Response::<4, 8, 4>::parse(&buf[..n]).unwrap();
+            assert!(response.answers.iter().any(is_subtype_ptr));
+        }
 
-        trace!("Handle response: {:?} {:?}", _from, response);
+        let start = Time::from_millis(4000);
+        let _ = server.handle(Input::Timeout(start), &mut buf);
 
-        ServiceInfo::from_answers::<SLEN>(&response.answers, &mut services);
+        for (subtype, expect_response) in [("_npu", false), ("_gpu", true)] {
+            let name = format!("{}._sub._test._tcp.local", subtype);
+            let mut request: Request<4, 4, 4> = Request {
+                id: 4711,
+                flags: Flags::standard_request(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+            request
+                .queries
+                .push(Query {
+                    name: Label::new(&name),
+                    qtype: QType::PTR,
+                    qclass: QClass::IN,
+                    unicast_response: false,
+                })
+                .unwrap();
 
-        services.retain(|s| is_matching_service(s, &self.services, &self.query_targets));
+            let mut packet = [0u8; 2048];
+            let mut w = Writer::<10>::new(&mut packet);
+            request.serialize(&mut w);
+            let packet = w.into_inner();
 
-        if services.len() > 1 {
-            warn!("More than one service in answers. This is not currently handled");
+            server.handle_input(Input::Packet(packet, REMOTE));
+            assert_eq!(server.responses.len(), expect_response as usize);
         }
 
-        if services.is_empty() {
-            Output::Timeout(self.poll_timeout())
-        } else {
-            Output::Remote(services.remove(0))
+        let at = server.responses[0].at;
+        match server.handle(Input::Timeout(at), &mut buf) {
+            Output::Packet(n, Cast::Multi { .. }) => {
+                // Only the PTR of the subtype, not the one of the type.
+                let response = Response::<4, 8, 4>::parse(&buf[..n]).unwrap();
+                let ptrs: std::vec::Vec<_> = response
+                    .answers
+                    .iter()
+                    .filter(|a| a.atype == QType::PTR)
+                    .collect();
+                assert_eq!(ptrs.len(), 1);
+                assert!(is_subtype_ptr(ptrs[0]));
+            }
+            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
         }
     }
-}
 
-fn is_same_network(ip: IpAddr, netmask: IpAddr, other: IpAddr) -> bool {
-    match (ip, netmask, other) {
-        (IpAddr::V4(ip), IpAddr::V4(mask), IpAddr::V4(other)) => {
-            (u32::from(ip) & u32::from(mask)) == (u32::from(other) & u32::from(mask))
+    #[test]
+    fn remote_with_long_subtype_is_kept() {
+        // The subtype name has five segments, more than LLEN 4, which doesn't limit the
+        // owned copy.
+        let remote = gpu_service();
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&remote, 120, &mut packet);
+
+        let mut server: Server<4, 8, 4, 4, 10, 8, 1> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => {
+                assert_eq!(info.instance_name(), remote.instance_name());
+                assert_eq!(info.port(), 1234);
+                assert!(info.subtypes().eq(remote.subtypes()));
+            }
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
         }
-        (IpAddr::V6(ip), IpAddr::V6(mask), IpAddr::V6(other)) => ip
-            .segments()
-            .iter()
-            .zip(mask.segments().iter())
-            .zip(other.segments().iter())
-            .all(|((&ip_seg, &mask_seg), &other_seg)| {
-                (ip_seg & mask_seg) == (other_seg & mask_seg)
-            }),
-        _ => false,
     }
-}
 
-fn is_matching_service<const LLEN: usize, const SLEN: usize>(
-    s1: &ServiceInfo<'_, LLEN>,
-    services: &Vec<ServiceInfo<'_, LLEN>, SLEN>,
-    query_targets: &Vec<QueryTarget<'_, LLEN>, SLEN>,
-) -> bool {
-    let mut handled_service = false;
-    let mut is_self = false;
+    #[test]
+    #[cfg(not(feature = "alloc"))]
+    fn remotes_not_fitting_cache_are_capped() {
+        let remotes = ["a", "b", "c", "d"].map(|name| {
+            ServiceInfo::<4>::new(
+                "_test._tcp.local",
+                name,
+                "other.local",
+                [192, 168, 0, 2],
+                [255, 255, 255, 0],
+                1234,
+            )
+        });
 
-    for s2 in services.iter() {
-        handled_service |= s1.service_type() == s2.service_type();
+        let packet = |order: &[usize], packet: &mut [u8; 2048]| -> usize {
+            let mut response: Response<4, 16, 4> = Response {
+                id: 0,
+                flags: Flags::standard_response(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+            for remote in order.iter().map(|i| &remotes[*i]) {
+                for answer in remote.as_answers(true) {
+                    if !response.answers.contains(&answer) {
+                        response.answers.push(answer).unwrap();
+                    }
+                }
+            }
+            let mut w = Writer::<10>::new(packet);
+            response.serialize(&mut w);
+            w.into_inner().len()
+        };
 
-        is_self |= s1.instance_name() == s2.instance_name()
-            && s1.ip_address() == s2.ip_address()
-            && s1.port() == s2.port();
+        let mut all = [0u8; 2048];
+        let n = packet(&[0, 1, 2, 3], &mut all);
+        let all = &all[..n];
+        let mut c_b = [0u8; 2048];
+        let n = packet(&[2, 1], &mut c_b);
+        let c_b = &c_b[..n];
+
+        // Room in the cache for one remote.
+        let mut server: Server<4, 16, 4, 4, 10, 1> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        let mut found = std::vec::Vec::new();
+        let mut collect = |output: Output<'static, 4, 4>| match output {
+            Output::Remote(info) => {
+                found.push(info.instance_name().to_string());
+                true
+            }
+            Output::Timeout(_) => false,
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        };
+
+        // One remote is cached, two more are waiting and the last is dropped.
+        collect(server.handle(Input::Packet(all, REMOTE), &mut buf));
+        while collect(server.poll_output(&mut buf)) {}
+
+        // The uncached ones again, and the one still waiting isn't queued twice.
+        collect(server.handle(Input::Packet(all, REMOTE), &mut buf));
+        collect(server.handle(Input::Packet(c_b, REMOTE), &mut buf));
+        while collect(server.poll_output(&mut buf)) {}
+
+        let names = ["a", "b", "c", "b", "c", "b"].map(|n| format!("{}._test._tcp.local", n));
+        assert_eq!(found, names);
     }
 
-    for qt in query_targets.iter() {
-        handled_service |= s1.service_type() == &qt.service_type;
+    #[test]
+    #[cfg(not(feature = "alloc"))]
+    fn remote_subtypes_left_out_without_sublen() {
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&gpu_service(), 120, &mut packet);
+
+        let mut server: Server<4, 8, 4, 4, 10> = Server::new(std::iter::empty());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => {
+                assert_eq!(info.port(), 1234);
+                assert_eq!(info.subtypes().count(), 0);
+            }
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
     }
 
-    handled_service && !is_self
-}
+    #[test]
+    fn subtype_browse_finds_only_instances_with_it() {
+        let mut server: Server<4, 16, 4, 4, 10, 8, 1> = Server::new(std::iter::empty());
+        server.query(
+            "_gpu._sub._test._tcp.local",
+            [192, 168, 0, 1],
+            [255, 255, 255, 0],
+        );
 
-#[cfg(feature = "defmt")]
-impl defmt::Format for Input<'_> {
-    fn format(&self, fmt: defmt::Formatter) {
-        use crate::format::FormatSocketAddr;
-        match self {
-            Input::Timeout(instant) => {
-                defmt::write!(fmt, "Timeout({:?})", instant);
+        let mut buf = [0u8; 2048];
+        let at = match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        let n = expect_packet(server.handle(Input::Timeout(at), &mut buf));
+        {
+            let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert_eq!(request.queries[0].name, "_gpu._sub._test._tcp.local");
+        }
+
+        let with_gpu = gpu_service();
+        let without_gpu = ServiceInfo::<4, 1>::new(
+            "_test._tcp.local",
+            "other",
+            "other.local",
+            [192, 168, 0, 3],
+            [255, 255, 255, 0],
+            1234,
+        );
+
+        let mut response: Response<4, 16, 4> = Response {
+            id: 0,
+            flags: Flags::standard_response(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        for remote in [&without_gpu, &with_gpu] {
+            for answer in remote.as_answers(true) {
+                response.answers.push(answer).unwrap();
             }
-            Input::Packet(data, addr) => {
-                defmt::write!(
-                    fmt,
-                    "Packet([..{} bytes], {:?})",
-                    data.len(),
-                    FormatSocketAddr(*addr)
+        }
+
+        let mut packet = [0u8; 2048];
+        let mut w = Writer::<10>::new(&mut packet);
+        response.serialize(&mut w);
+        let packet = w.into_inner();
+
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Remote(info) => {
+                assert_eq!(
+                    info.instance_name().to_string(),
+                    with_gpu.instance_name().to_string()
                 );
+                assert_eq!(info.service_type(), "_test._tcp.local");
+                let subtypes = info.subtypes().map(|l| l.to_string());
+                assert!(subtypes.eq(with_gpu.subtypes().map(|l| l.to_string())));
             }
+            other => panic!("expected Remote, got {:?}", OutputDebug(other)),
+        }
+        match server.handle(Input::Timeout(at), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        }
+
+        // Browsing the type too, a response without the subtype record is not a change.
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service(), 120, &mut packet);
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
         }
     }
-}
 
-#[cfg(feature = "defmt")]
-impl defmt::Format for Cast {
-    fn format(&self, fmt: defmt::Formatter) {
-        use crate::format::{FormatIpAddr, FormatSocketAddr};
-        match self {
-            Cast::Multi { from } => {
-                defmt::write!(fmt, "Multi {{ from:{:?} }}", FormatIpAddr(*from));
+    #[test]
+    fn service_type_enumeration_is_answered() {
+        let other_type = ServiceInfo::<4>::new(
+            "_other._udp.local",
+            "martin_other",
+            "mini.local",
+            [192, 168, 0, 1],
+            [255, 255, 255, 0],
+            4321,
+        );
+        let mut same_type = test_service_port(5678);
+        same_type.rename();
+
+        let services = [test_service(), same_type, other_type];
+        let mut server: Server<4, 4, 4, 4, 10> = Server::new(services.into_iter());
+
+        let mut buf = [0u8; 2048];
+        for i in 0..=3 {
+            let now = Time::from_millis(PROBE_START + i * PROBE_INTERVAL);
+            expect_packet(server.handle(Input::Timeout(now), &mut buf));
+        }
+
+        // Without alloc, the announcement of all three takes several packets.
+        let start = Time::from_millis(4000);
+        while !matches!(
+            server.handle(Input::Timeout(start), &mut buf),
+            Output::Timeout(_)
+        ) {}
+
+        // Without known answers, and with the type of the test service known.
+        let expected: [&[&str]; 2] = [
+            &["_other._udp.local", "_test._tcp.local"],
+            &["_other._udp.local"],
+        ];
+
+        for (known, expected) in [false, true].into_iter().zip(expected) {
+            let test_service = test_service();
+            let mut request: Request<4, 4, 4> = Request {
+                id: 4711,
+                flags: Flags::standard_request(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+            request
+                .queries
+                .push(Query {
+                    name: Label::new(SERVICE_TYPES),
+                    qtype: QType::PTR,
+                    qclass: QClass::IN,
+                    unicast_response: false,
+                })
+                .unwrap();
+
+            if known {
+                let known = test_service.ptr_answer_for(&request.queries[0].name);
+                request.answers.push(known).unwrap();
             }
-            Cast::Uni { from, target } => {
-                defmt::write!(
-                    fmt,
-                    "Uni {{ from:{:?}, target:{:?} }}",
-                    FormatIpAddr(*from),
-                    FormatSocketAddr(*target)
-                );
+
+            let mut packet = [0u8; 2048];
+            let mut w = Writer::<10>::new(&mut packet);
+            request.serialize(&mut w);
+            let packet = w.into_inner();
+
+            let at = match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+                Output::Timeout(t) => t,
+                other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+            };
+
+            match server.handle(Input::Timeout(at), &mut buf) {
+                Output::Packet(n, Cast::Multi { .. }) => {
+                    let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                    let mut types: std::vec::Vec<_> = response
+                        .answers
+                        .iter()
+                        .map(|a| {
+                            assert_eq!(a.name, SERVICE_TYPES);
+                            match &a.record {
+                                Record::PTR(ptr) => ptr.name.to_string(),
+                                _ => panic!("expected PTR, got {:?}", a),
+                            }
+                        })
+                        .collect();
+                    types.sort();
+                    assert_eq!(types, expected);
+                }
+                other => panic!("expected Packet, got {:?}", OutputDebug(other)),
             }
         }
     }
-}
-
-#[cfg(all(feature = "std", test))]
-mod test {
-    use super::*;
 
     #[test]
-    fn discovery_only_query_fires_immediately() {
+    fn service_types_are_reported_once() {
         let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
-        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+        server.query_service_types([192, 168, 0, 1], [255, 255, 255, 0]);
 
         let mut buf = [0u8; 2048];
+        let at = match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        let n = expect_packet(server.handle(Input::Timeout(at), &mut buf));
+        {
+            let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            assert_eq!(request.queries[0].name, SERVICE_TYPES);
+            assert_eq!(request.queries[0].qtype, QType::PTR);
+        }
 
-        // The very first timeout at time 0 should produce a query packet, not a timeout.
-        match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
-            Output::Packet(n, Cast::Multi { from }) => {
-                assert!(n > 0, "packet should have content");
-                assert_eq!(from, IpAddr::from([192, 168, 0, 1]));
+        let mut response: Response<4, 4, 4> = Response {
+            id: 0,
+            flags: Flags::standard_response(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        for service_type in ["_test._tcp.local", "_other._udp.local"] {
+            let answer = Answer {
+                name: Label::new(SERVICE_TYPES),
+                atype: QType::PTR,
+                aclass: QClass::IN,
+                cache_flush: false,
+                ttl: 4500,
+                record: Record::PTR(PTR {
+                    name: Label::new(service_type),
+                }),
+            };
+            response.answers.push(answer).unwrap();
+        }
+
+        let mut packet = [0u8; 2048];
+        let mut w = Writer::<10>::new(&mut packet);
+        response.serialize(&mut w);
+        let packet = w.into_inner();
+
+        let mut found = std::vec::Vec::new();
+
+        let mut input = Input::Packet(packet, REMOTE);
+        loop {
+            match server.handle(input, &mut buf) {
+                Output::ServiceType(service_type) => found.push(service_type.to_string()),
+                Output::Timeout(_) => break,
+                other => panic!("expected ServiceType, got {:?}", OutputDebug(other)),
             }
-            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+            input = Input::Timeout(at);
+        }
+
+        assert_eq!(found, ["_test._tcp.local", "_other._udp.local"]);
+
+        // The same types again are not reported.
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(_) => {}
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
         }
     }
 
     #[test]
-    fn discovery_only_no_advertisement() {
+    fn host_query_is_answered() {
+        let mut server = announced_server();
+
+        let mut buf = [0u8; 2048];
+        let start = Time::from_millis(4000);
+        let _ = server.handle(Input::Timeout(start), &mut buf);
+
+        for (hostname, qtype, expect_response) in [
+            ("other.local", QType::A, false),
+            ("mini.local", QType::AAAA, false),
+            ("MINI.local", QType::A, true),
+        ] {
+            let mut request: Request<4, 4, 4> = Request {
+                id: 4711,
+                flags: Flags::standard_request(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+            request
+                .queries
+                .push(Query {
+                    name: Label::new(hostname),
+                    qtype,
+                    qclass: QClass::IN,
+                    unicast_response: false,
+                })
+                .unwrap();
+
+            let mut packet = [0u8; 2048];
+            let mut w = Writer::<10>::new(&mut packet);
+            request.serialize(&mut w);
+            let packet = w.into_inner();
+
+            // Address records are unique, and answered right away.
+            match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+                Output::Packet(n, Cast::Multi { .. }) if expect_response => {
+                    let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                    assert_eq!(response.answers.len(), 1);
+                    let answer = &response.answers[0];
+                    assert_eq!(answer.name, "mini.local");
+                    assert_eq!(
+                        answer.record,
+                        Record::A(A {
+                            address: Ipv4Addr::new(192, 168, 0, 1)
+                        })
+                    );
+                }
+                Output::Timeout(_) if !expect_response => {}
+                other => panic!("unexpected {:?}", OutputDebug(other)),
+            }
+        }
+    }
+
+    #[test]
+    fn resolve_host_reports_addresses() {
         let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
-        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+        server.resolve_host("nugget.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let at = match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
+            Output::Timeout(t) => t,
+            other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+        let n = expect_packet(server.handle(Input::Timeout(at), &mut buf));
+        {
+            let request = Request::<4, 4, 4>::parse(&buf[..n]).unwrap();
+            let qtypes: std::vec::Vec<_> = request.queries.iter().map(|q| q.qtype).collect();
+            assert_eq!(qtypes, [QType::A, QType::AAAA]);
+            assert!(request.queries.iter().all(|q| q.name == "nugget.local"));
+        }
+
+        let v6: Ipv6Addr = "fe80::9".parse().unwrap();
+        let mut response: Response<4, 4, 4> = Response {
+            id: 0,
+            flags: Flags::standard_response(),
+            queries: Vec::new(),
+            answers: Vec::new(),
+            authorities: Vec::new(),
+            additionals: Vec::new(),
+        };
+        let records = [
+            (
+                QType::A,
+                Record::A(A {
+                    address: Ipv4Addr::new(192, 168, 0, 9),
+                }),
+            ),
+            (QType::AAAA, Record::AAAA(AAAA { address: v6 })),
+        ];
+        for (atype, record) in records {
+            let answer = Answer {
+                name: Label::new("nugget.local"),
+                atype,
+                aclass: QClass::IN,
+                cache_flush: true,
+                ttl: 120,
+                record,
+            };
+            response.answers.push(answer).unwrap();
+        }
+
+        let mut packet = [0u8; 2048];
+        let mut w = Writer::<10>::new(&mut packet);
+        response.serialize(&mut w);
+        let packet = w.into_inner();
+
+        let mut found = std::vec::Vec::new();
+
+        let mut input = Input::Packet(packet, REMOTE);
+        loop {
+            match server.handle(input, &mut buf) {
+                Output::Resolved(hostname, ip) => {
+                    assert_eq!(hostname, "nugget.local");
+                    found.push(ip);
+                }
+                Output::Timeout(_) => break,
+                other => panic!("expected Resolved, got {:?}", OutputDebug(other)),
+            }
+            input = Input::Timeout(at);
+        }
+
+        assert_eq!(
+            found,
+            [IpAddr::V4(Ipv4Addr::new(192, 168, 0, 9)), IpAddr::V6(v6)]
+        );
+
+        // The host is resolved, and not queried for anymore.
+        assert!(server.query_targets.is_empty());
+        assert!(server.next_query().is_none());
+    }
+
+    #[test]
+    fn qu_question_is_answered_with_unicast() {
+        let mut server = announced_server();
 
         let mut buf = [0u8; 2048];
+        let mut packet = [0u8; 2048];
+        let packet = ptr_query_packet(true, &mut packet);
 
-        // First handle produces a query packet.
-        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+        let start = Time::from_millis(4000);
+        let _ = server.handle(Input::Timeout(start), &mut buf);
 
-        // Second handle should produce a timeout (no advertisement, no more queries yet).
-        match server.handle(Input::Timeout(Time::from_millis(1)), &mut buf) {
-            Output::Timeout(_) => {}
+        // The records were multicast recently, so the querier gets them directly.
+        let at = match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(t) => t,
             other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
+        };
+
+        match server.handle(Input::Timeout(at), &mut buf) {
+            Output::Packet(n, Cast::Uni { target, .. }) => {
+                assert_eq!(target, REMOTE);
+                let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                assert_eq!(response.id, 0);
+                assert!(response.queries.is_empty());
+                for answer in response.answers.iter() {
+                    assert_eq!(answer.cache_flush, answer.atype != QType::PTR);
+                }
+            }
+            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
         }
     }
 
     #[test]
-    fn empty_server_does_nothing() {
-        let mut server: Server<4, 4, 4, 4, 10> = Server::new(std::iter::empty());
+    fn legacy_query_is_answered_directly() {
+        let mut server = announced_server();
 
         let mut buf = [0u8; 2048];
+        let mut packet = [0u8; 2048];
+        let packet = ptr_query_packet(false, &mut packet);
 
-        // With no services and no query targets, the server should just return a timeout.
-        match server.handle(Input::Timeout(Time::from_millis(0)), &mut buf) {
-            Output::Timeout(_) => {}
+        let start = Time::from_millis(4000);
+        let _ = server.handle(Input::Timeout(start), &mut buf);
+
+        // Not from the mDNS port.
+        let legacy = SocketAddr::new(REMOTE.ip(), 49152);
+
+        match server.handle(Input::Packet(packet, legacy), &mut buf) {
+            Output::Packet(n, Cast::Uni { target, .. }) => {
+                assert_eq!(target, legacy);
+                let response = Response::<4, 4, 4>::parse(&buf[..n]).unwrap();
+                assert_eq!(response.id, 4711);
+                assert_eq!(response.queries.len(), 1);
+                assert_eq!(response.queries[0].qtype, QType::PTR);
+                for answer in response.records() {
+                    assert!(!answer.cache_flush);
+                    assert!(answer.ttl <= 10);
+                }
+            }
+            other => panic!("expected Packet, got {:?}", OutputDebug(other)),
+        }
+    }
+
+    #[test]
+    fn known_answer_suppresses_response() {
+        let service = test_service();
+        let mut packet = [0u8; 2048];
+        let mut server = announced_server();
+
+        let mut buf = [0u8; 2048];
+
+        for (ttl, expect_response) in [(4500, false), (1000, true)] {
+            let mut request: Request<4, 4, 4> = Request {
+                id: 4711,
+                flags: Flags::standard_request(),
+                queries: Vec::new(),
+                answers: Vec::new(),
+                authorities: Vec::new(),
+                additionals: Vec::new(),
+            };
+            request
+                .queries
+                .push(Query {
+                    name: service.service_type(),
+                    qtype: QType::PTR,
+                    qclass: QClass::IN,
+                    unicast_response: false,
+                })
+                .unwrap();
+            let mut known = service.ptr_answer();
+            known.ttl = ttl;
+            request.answers.push(known).unwrap();
+
+            let mut w = Writer::<10>::new(&mut packet);
+            request.serialize(&mut w);
+            let packet = w.into_inner();
+
+            let _ = server.handle(Input::Packet(packet, REMOTE), &mut buf);
+            assert_eq!(!server.responses.is_empty(), expect_response);
+        }
+    }
+
+    #[test]
+    fn remote_goodbye_is_lost_after_one_second() {
+        let mut server: Server<4, 4, 4, 4, 10> =
+            Server::new_with_config(std::iter::empty(), no_jitter());
+        server.query("_test._tcp.local", [192, 168, 0, 1], [255, 255, 255, 0]);
+
+        let mut buf = [0u8; 2048];
+        let _ = server.handle(Input::Timeout(Time::from_millis(0)), &mut buf);
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service(), 120, &mut packet);
+        let _ = server.handle(Input::Packet(packet, REMOTE), &mut buf);
+
+        let mut packet = [0u8; 2048];
+        let packet = response_packet(&test_service(), 0, &mut packet);
+        match server.handle(Input::Packet(packet, REMOTE), &mut buf) {
+            Output::Timeout(t) => assert_eq!(t, Time::from_millis(1000)),
             other => panic!("expected Timeout, got {:?}", OutputDebug(other)),
         }
+
+        match server.handle(Input::Timeout(Time::from_millis(1000)), &mut buf) {
+            Output::RemoteLost(info) => assert_eq!(info.port(), 1234),
+            other => panic!("expected RemoteLost, got {:?}", OutputDebug(other)),
+        }
     }
 
     // Helper for Debug formatting in panic messages.
-    struct OutputDebug<'a, const LLEN: usize, const SLEN: usize>(Output<'a, LLEN, SLEN>);
+    struct OutputDebug<'a, const LLEN: usize, const SLEN: usize, const SUBLEN: usize>(
+        Output<'a, LLEN, SLEN, SUBLEN>,
+    );
 
-    impl<const LLEN: usize, const SLEN: usize> core::fmt::Debug for OutputDebug<'_, LLEN, SLEN> {
+    impl<const LLEN: usize, const SLEN: usize, const SUBLEN: usize> core::fmt::Debug
+        for OutputDebug<'_, LLEN, SLEN, SUBLEN>
+    {
         fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
             match &self.0 {
                 Output::Packet(n, cast) => write!(f, "Packet({}, {:?})", n, cast),
                 Output::Timeout(t) => write!(f, "Timeout({:?})", t),
                 Output::Remote(s) => write!(f, "Remote({:?})", s),
+                Output::RemoteLost(s) => write!(f, "RemoteLost({:?})", s),
+                Output::Renamed(s) => write!(f, "Renamed({:?})", s),
+                Output::ServiceType(l) => write!(f, "ServiceType({:?})", l),
+                Output::Resolved(l, ip) => write!(f, "Resolved({:?}, {})", l, ip),
+                Output::Overflow(n) => write!(f, "Overflow({})", n),
             }
         }
     }
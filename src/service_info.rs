@@ -1,26 +1,114 @@
 use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
-use crate::dns::{self, Answer, Label, QClass, QType, Record};
+use core::fmt::Write;
+
+use crate::dns::{self, Answer, Label, OwnedLabel, QClass, QType, Record, MAX_SEGMENT_LEN};
+use crate::server::is_same_network;
+use crate::string::String;
+use crate::txt::Txt;
 use crate::vec::Vec;
 
 /// Information about a service to declare over mDNS.
-#[derive(Debug)]
-pub struct ServiceInfo<'a, const LLEN: usize = 4> {
-    service_type: Label<'a, LLEN>,
-    instance_name: Label<'a, LLEN>,
-    hostname: Label<'a, LLEN>,
-    ip_address: IpAddr,
-    netmask: IpAddr,
+///
+/// * `LLEN` - Max number of segments for a Label, like for the [`Server`][crate::Server].
+/// * `SUBLEN` - Max number of subtypes, see [`add_subtype`][Self::add_subtype]. Only used
+///   if not **alloc**. Defaults to 0, which keeps the subtypes from taking up room in
+///   every service.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInfo<'a, const LLEN: usize = 4, const SUBLEN: usize = 0> {
+    service_type: Name<'a, LLEN>,
+    instance_name: Name<'a, LLEN>,
+    hostname: Name<'a, LLEN>,
+    addresses: Vec<HostAddr, MAX_ADDRESSES>,
     port: u16,
+    txt: Txt,
+    subtypes: Vec<Name<'a, LLEN>, SUBLEN>,
+}
+
+/// A name of the service, borrowed or owned.
+///
+/// Names from [`ServiceInfo::new`] borrow the given strs, and names of remote
+/// services borrow the packet until they are copied with
+/// [`try_to_owned`][ServiceInfo::try_to_owned].
+// Without alloc there is no Box, and the owned names are the ones to keep anyway.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum Name<'a, const LLEN: usize> {
+    Borrowed(Label<'a, LLEN>),
+    Owned(OwnedLabel),
+}
+
+impl<const LLEN: usize> Name<'_, LLEN> {
+    fn label(&self) -> Label<'_, LLEN> {
+        match self {
+            Name::Borrowed(label) => label.clone(),
+            Name::Owned(owned) => owned.label(),
+        }
+    }
+
+    fn try_to_owned(&self) -> Option<Name<'static, LLEN>> {
+        let owned = match self {
+            Name::Borrowed(label) => label.try_to_owned()?,
+            Name::Owned(owned) => owned.clone(),
+        };
+        Some(Name::Owned(owned))
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Name::Borrowed(label) => label.is_empty(),
+            Name::Owned(owned) => owned.is_empty(),
+        }
+    }
+}
+
+impl<const LLEN: usize> PartialEq for Name<'_, LLEN> {
+    fn eq(&self, other: &Self) -> bool {
+        self.label() == other.label()
+    }
+}
+
+impl<const LLEN: usize> PartialEq<Label<'_, LLEN>> for Name<'_, LLEN> {
+    fn eq(&self, other: &Label<'_, LLEN>) -> bool {
+        self.label() == *other
+    }
+}
+
+impl<const LLEN: usize> Eq for Name<'_, LLEN> {}
+
+impl<const LLEN: usize> core::fmt::Debug for Name<'_, LLEN> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&self.label(), f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const LLEN: usize> defmt::Format for Name<'_, LLEN> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::Format::format(&self.label(), fmt)
+    }
+}
+
+/// An address of the host, with the netmask of its network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HostAddr {
+    ip: IpAddr,
+    netmask: IpAddr,
 }
 
+/// Max number of addresses for one host. Only used if not **alloc**.
+const MAX_ADDRESSES: usize = 4;
+
+/// Name for enumerating the service types on the network (RFC 6763 9).
+pub(crate) const SERVICE_TYPES: &str = "_services._dns-sd._udp.local";
+
 const DEFAULT_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
 const NETMASK_FULL_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(255, 255, 255, 255));
 const NETMASK_FULL_V6: IpAddr = IpAddr::V6(Ipv6Addr::new(
     0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff, 0xffff,
 ));
 
-impl<'a, const LLEN: usize> ServiceInfo<'a, LLEN> {
+impl<'a, const LLEN: usize, const SUBLEN: usize> ServiceInfo<'a, LLEN, SUBLEN> {
     /// Creates information about a new service.
     ///
     /// ```
@@ -48,49 +136,208 @@ impl<'a, const LLEN: usize> ServiceInfo<'a, LLEN> {
         let mut i = service_type.clone();
         i.push_front(instance_name);
 
+        let mut addresses = Vec::new();
+        // unwrap: there is always room for one address.
+        addresses
+            .push(HostAddr {
+                ip: ip_address.into(),
+                netmask: netmask.into(),
+            })
+            .unwrap();
+
         Self {
-            service_type,
-            instance_name: i,
-            hostname: Label::new(hostname),
-            ip_address: ip_address.into(),
-            netmask: netmask.into(),
+            service_type: Name::Borrowed(service_type),
+            instance_name: Name::Borrowed(i),
+            hostname: Name::Borrowed(Label::new(hostname)),
+            addresses,
             port,
+            txt: Txt::new(),
+            subtypes: Vec::new(),
+        }
+    }
+
+    /// Add another address for the host name.
+    ///
+    /// Use this for dual-stack hosts with both IPv4 and IPv6, or hosts on several
+    /// interfaces. Each address is advertised on the network it belongs to.
+    ///
+    /// Returns `false` if the address is already added, or if there is no room for
+    /// it. Without **alloc**, a host has at most 4 addresses.
+    pub fn add_address(
+        &mut self,
+        ip_address: impl Into<IpAddr>,
+        netmask: impl Into<IpAddr>,
+    ) -> bool {
+        let ip = ip_address.into();
+
+        if self.addresses.iter().any(|a| a.ip == ip) {
+            return false;
+        }
+
+        let address = HostAddr {
+            ip,
+            netmask: netmask.into(),
+        };
+
+        self.addresses.push(address).is_ok()
+    }
+
+    /// Swap the address `old` for another. Returns `false` if there was no `old`.
+    pub(crate) fn replace_address(&mut self, old: IpAddr, ip: IpAddr, netmask: IpAddr) -> bool {
+        let Some(idx) = self.addresses.iter().position(|a| a.ip == old) else {
+            return false;
+        };
+
+        // The new address might already be there.
+        if self.addresses.iter().any(|a| a.ip == ip) {
+            self.addresses.remove(idx);
+        } else {
+            self.addresses[idx] = HostAddr { ip, netmask };
+        }
+
+        true
+    }
+
+    /// Drop the address `ip`. Returns `false` if there was no such address.
+    pub(crate) fn remove_address(&mut self, ip: IpAddr) -> bool {
+        let before = self.addresses.len();
+        self.addresses.retain(|a| a.ip != ip);
+        self.addresses.len() != before
+    }
+
+    /// Whether the host has no address left.
+    pub(crate) fn has_no_address(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// Set the entries of the TXT record.
+    pub fn with_txt(mut self, txt: Txt) -> Self {
+        self.txt = txt;
+        self
+    }
+
+    /// Add a subtype of the service type (RFC 6763 7.1).
+    ///
+    /// The subtype `_printer` of `_http._tcp.local` is advertised as an extra PTR
+    /// record for `_printer._sub._http._tcp.local`. Clients browsing for that name
+    /// only find the instances with the subtype.
+    ///
+    /// Returns `false` if the subtype is already added, or if there is no room for
+    /// it. Without **alloc**, a service has at most `SUBLEN` subtypes. The subtype
+    /// names need two more segments of `LLEN` than the service type.
+    ///
+    /// ```
+    /// use opslag::ServiceInfo;
+    ///
+    /// let mut info = ServiceInfo::<4, 1>::new(
+    ///    "_my-service._udp.local",
+    ///    "instance01",
+    ///    "nugget.local",
+    ///    [192, 168, 0, 3],
+    ///    [255, 255, 255, 0],
+    ///    1234,
+    /// );
+    /// assert!(info.add_subtype("_gpu"));
+    ///
+    /// let subtype = info.subtypes().next().unwrap();
+    /// assert_eq!(subtype, "_gpu._sub._my-service._udp.local");
+    /// ```
+    pub fn add_subtype(&mut self, subtype: &'a str) -> bool {
+        let name = match &self.service_type {
+            Name::Borrowed(service_type) => {
+                let mut name = service_type.clone();
+                if !name.push_front("_sub") || !name.push_front(subtype) {
+                    return false;
+                }
+                Name::Borrowed(name)
+            }
+            Name::Owned(service_type) => {
+                let segments = [subtype, "_sub"].into_iter().chain(service_type.iter());
+                let Some(name) = OwnedLabel::from_segments(segments) else {
+                    return false;
+                };
+                Name::Owned(name)
+            }
+        };
+        self.push_subtype(name)
+    }
+
+    fn push_subtype(&mut self, name: Name<'a, LLEN>) -> bool {
+        if self.subtypes.contains(&name) {
+            return false;
+        }
+        self.subtypes.push(name).is_ok()
+    }
+
+    /// Keep the subtypes of `old` along with the ones of `self`.
+    ///
+    /// Responses only have the subtype records when asked for, so a remote without
+    /// subtypes might just not have sent them.
+    pub(crate) fn keep_subtypes(&mut self, old: &ServiceInfo<'a, LLEN, SUBLEN>) {
+        let new = core::mem::replace(&mut self.subtypes, old.subtypes.clone());
+        for name in new.iter() {
+            self.push_subtype(name.clone());
         }
     }
 
     /// The type of service.
     ///
     /// Example: `_my-service._tcp.local`
-    pub fn service_type(&self) -> &Label<'a, LLEN> {
-        &self.service_type
+    pub fn service_type(&self) -> Label<'_, LLEN> {
+        self.service_type.label()
     }
 
     /// The instance name.
     ///
     /// Example: `myinstance01`
-    pub fn instance_name(&self) -> &Label<'a, LLEN> {
-        &self.instance_name
+    pub fn instance_name(&self) -> Label<'_, LLEN> {
+        self.instance_name.label()
+    }
+
+    /// The subtypes of the service type.
+    ///
+    /// Example: `_printer._sub._http._tcp.local`
+    pub fn subtypes(&self) -> impl Iterator<Item = Label<'_, LLEN>> {
+        self.subtypes.iter().map(|name| name.label())
     }
 
     /// The host name the service is running on.
     ///
     /// Example: `Martin's Macbook Air.local`
-    pub fn hostname(&self) -> &Label<'a, LLEN> {
-        &self.hostname
+    pub fn hostname(&self) -> Label<'_, LLEN> {
+        self.hostname.label()
     }
 
     /// Corresponding IP address for the host name.
     ///
+    /// This is the first of the [`ip_addresses`][Self::ip_addresses()].
+    ///
     /// Example: `192.160.10.24`
     pub fn ip_address(&self) -> IpAddr {
-        self.ip_address
+        self.addresses.first().map(|a| a.ip).unwrap_or(DEFAULT_ADDR)
     }
 
-    /// The netmask, if known.
+    /// The netmask of the first IP address.
     ///
-    /// Otherwise returns a "full" mask, ie `255.255.255.255`.
+    /// Remote services have a "full" mask, ie `255.255.255.255`, since the netmask
+    /// isn't part of the DNS records. Without any address, this is `0.0.0.0`.
     pub fn netmask(&self) -> IpAddr {
-        self.netmask
+        self.addresses
+            .first()
+            .map(|a| a.netmask)
+            .unwrap_or(DEFAULT_ADDR)
+    }
+
+    /// All IP addresses for the host name.
+    ///
+    /// Example: `192.160.10.24`, `fe80::1`
+    pub fn ip_addresses(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.addresses.iter().map(|a| a.ip)
+    }
+
+    /// The addresses with their netmasks.
+    pub(crate) fn networks(&self) -> impl Iterator<Item = (IpAddr, IpAddr)> + '_ {
+        self.addresses.iter().map(|a| (a.ip, a.netmask))
     }
 
     /// Port the service is running on.
@@ -100,114 +347,285 @@ impl<'a, const LLEN: usize> ServiceInfo<'a, LLEN> {
         self.port
     }
 
-    pub(crate) fn ptr_answer(&'a self, _aclass: QClass) -> Answer<'a, LLEN> {
+    /// The entries of the TXT record.
+    ///
+    /// Example: `version=1.2`
+    pub fn txt(&self) -> &Txt {
+        &self.txt
+    }
+
+    /// Rename the instance after a name conflict.
+    ///
+    /// `martin_test` becomes `martin_test (2)`, `martin_test (2)` becomes
+    /// `martin_test (3)` and so forth.
+    pub(crate) fn rename(&mut self) -> bool {
+        let mut name: String<MAX_SEGMENT_LEN> = String::new();
+
+        let renamed = {
+            let instance_name = self.instance_name.label();
+            let mut segments = instance_name.segments();
+
+            let Some(current) = segments.next() else {
+                return false;
+            };
+
+            let (base, n) = split_rename_suffix(current);
+
+            let mut suffix: String<16> = String::new();
+            // unwrap: " (<u32>)" always fits in 16 bytes.
+            core::write!(suffix, " ({})", n.saturating_add(1)).unwrap();
+
+            // Truncate the base to leave room for the suffix.
+            let mut end = base.len().min(MAX_SEGMENT_LEN - suffix.len());
+            while !base.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            // unwrap: we made sure it fits above.
+            name.push_str(&base[..end]).unwrap();
+            name.push_str(&suffix).unwrap();
+
+            // The rest of the name stays as it is on the wire.
+            OwnedLabel::from_segments([&name[..]].into_iter().chain(segments))
+        };
+
+        let Some(renamed) = renamed else {
+            return false;
+        };
+        self.instance_name = Name::Owned(renamed);
+        true
+    }
+
+    /// Copy the service info into one that doesn't borrow anything.
+    ///
+    /// Useful to keep information about remote services after the buffers it
+    /// was parsed from are gone. See [`Label::try_to_owned`] for when it fails. Subtypes
+    /// that can't be copied are left out.
+    ///
+    /// ```
+    /// use opslag::ServiceInfo;
+    ///
+    /// let owned: ServiceInfo<'static, 4> = {
+    ///     let instance = String::from("instance01");
+    ///     let info = ServiceInfo::<4>::new(
+    ///         "_my-service._udp.local",
+    ///         &instance,
+    ///         "nugget.local",
+    ///         [192, 168, 0, 3],
+    ///         [255, 255, 255, 0],
+    ///         1234,
+    ///     );
+    ///     info.try_to_owned().unwrap()
+    /// };
+    ///
+    /// assert_eq!(owned.instance_name(), "instance01._my-service._udp.local");
+    /// ```
+    pub fn try_to_owned(&self) -> Option<ServiceInfo<'static, LLEN, SUBLEN>> {
+        Some(ServiceInfo {
+            service_type: self.service_type.try_to_owned()?,
+            instance_name: self.instance_name.try_to_owned()?,
+            hostname: self.hostname.try_to_owned()?,
+            addresses: self.addresses.clone(),
+            port: self.port,
+            txt: self.txt.clone(),
+            subtypes: {
+                let mut subtypes = Vec::new();
+                // A subtype that doesn't fit is left out, rather than the whole service.
+                for owned in self.subtypes.iter().filter_map(|name| name.try_to_owned()) {
+                    let _ = subtypes.push(owned);
+                }
+                subtypes
+            },
+        })
+    }
+
+    /// The PTR record of the type.
+    #[cfg(all(feature = "std", test))]
+    pub(crate) fn ptr_answer(&'a self) -> Answer<'a, LLEN> {
+        self.ptr_answer_for(&self.service_type())
+    }
+
+    /// The names of the PTR records, the type followed by the subtypes.
+    pub(crate) fn ptr_names(&self) -> impl Iterator<Item = Label<'_, LLEN>> {
+        [&self.service_type]
+            .into_iter()
+            .chain(self.subtypes.iter())
+            .map(|name| name.label())
+    }
+
+    /// The PTR records of the type and the subtypes.
+    pub(crate) fn ptr_answers(&'a self) -> impl Iterator<Item = Answer<'a, LLEN>> + 'a {
+        self.ptr_names().map(move |name| self.ptr_answer_for(&name))
+    }
+
+    /// The PTR record for `name`, one of the [`ptr_names`][Self::ptr_names] or
+    /// [`SERVICE_TYPES`].
+    ///
+    /// PTR records are shared with other instances of the type, and never have the
+    /// cache-flush bit (RFC 6762 10.2).
+    pub(crate) fn ptr_answer_for(&'a self, name: &Label<'a, LLEN>) -> Answer<'a, LLEN> {
+        // The service type enumeration points to the type rather than the instance.
+        let target = if *name == SERVICE_TYPES {
+            self.service_type()
+        } else {
+            self.instance_name()
+        };
+
         Answer {
-            name: self.service_type.clone(),
+            name: name.clone(),
             atype: QType::PTR,
             aclass: QClass::IN,
+            cache_flush: false,
             ttl: 4500,
-            record: Record::PTR(dns::PTR {
-                name: self.instance_name.clone(),
-            }),
+            record: Record::PTR(dns::PTR { name: target }),
         }
     }
 
-    pub(crate) fn srv_answer(&'a self, aclass: QClass) -> Answer<'a, LLEN> {
+    pub(crate) fn srv_answer(&'a self, cache_flush: bool) -> Answer<'a, LLEN> {
         Answer {
-            name: self.instance_name.clone(),
+            name: self.instance_name(),
             atype: QType::SRV,
-            aclass,
+            aclass: QClass::IN,
+            cache_flush,
             ttl: 120,
             record: Record::SRV(dns::SRV {
                 priority: 0,
                 weight: 0,
                 port: self.port,
-                target: self.hostname.clone(),
+                target: self.hostname(),
             }),
         }
     }
 
-    pub(crate) fn txt_answer(&'a self, aclass: QClass) -> Answer<'a, LLEN> {
+    pub(crate) fn txt_answer(&'a self, cache_flush: bool) -> Answer<'a, LLEN> {
         Answer {
-            name: self.instance_name.clone(),
+            name: self.instance_name(),
             atype: QType::TXT,
-            aclass,
+            aclass: QClass::IN,
+            cache_flush,
             ttl: 120,
-            record: Record::TXT(dns::TXT { text: "\0" }),
+            record: Record::TXT(dns::TXT {
+                data: self.txt.rdata(),
+            }),
         }
     }
 
-    pub(crate) fn ip_answer(&'a self, aclass: QClass) -> Answer<'a, LLEN> {
-        match self.ip_address {
+    /// One address record per address.
+    pub(crate) fn ip_answers(
+        &'a self,
+        cache_flush: bool,
+    ) -> impl Iterator<Item = Answer<'a, LLEN>> + 'a {
+        self.ip_addresses()
+            .map(move |ip| self.ip_answer(ip, cache_flush))
+    }
+
+    pub(crate) fn ip_answer(&'a self, ip: IpAddr, cache_flush: bool) -> Answer<'a, LLEN> {
+        match ip {
             IpAddr::V4(address) => Answer {
-                name: self.hostname.clone(),
+                name: self.hostname(),
                 atype: QType::A,
-                aclass,
+                aclass: QClass::IN,
+                cache_flush,
                 ttl: 120,
                 record: Record::A(dns::A { address }),
             },
             IpAddr::V6(address) => Answer {
-                name: self.hostname.clone(),
+                name: self.hostname(),
                 atype: QType::AAAA,
                 aclass: QClass::IN,
+                cache_flush,
                 ttl: 120,
                 record: Record::AAAA(dns::AAAA { address }),
             },
         }
     }
 
-    pub(crate) fn from_answers<const SLEN: usize>(
-        answers: &[Answer<'a, LLEN>],
+    /// Collect complete services from the records of a response.
+    ///
+    /// The records can come from any section, since many responders put SRV, TXT
+    /// and A records in the additional section.
+    pub(crate) fn from_answers<'b, const SLEN: usize>(
+        answers: impl Iterator<Item = &'b Answer<'a, LLEN>> + Clone,
         output: &mut Vec<Self, SLEN>,
-    ) {
-        // Step 1: Process PTR records
-        for answer in answers {
+    ) where
+        'a: 'b,
+    {
+        // Step 1: Process PTR records, of the type or of a subtype
+        for answer in answers.clone() {
+            if answer.name == SERVICE_TYPES {
+                continue;
+            }
+
             if let Record::PTR(ptr) = &answer.record {
-                let instance_name = ptr.name.clone();
-                let service_type = answer.name.clone();
-                let _ = output.push(ServiceInfo {
-                    service_type,
-                    instance_name,
-                    hostname: Label::default(),
-                    ip_address: DEFAULT_ADDR,
-                    netmask: DEFAULT_ADDR,
-                    port: 0,
-                });
+                let (service_type, subtype) = match subtype_parent(&answer.name) {
+                    Some(parent) => (parent, Some(Name::Borrowed(answer.name.clone()))),
+                    None => (answer.name.clone(), None),
+                };
+
+                let existing = output
+                    .iter()
+                    .position(|stub| stub.instance_name == ptr.name);
+
+                let idx = match existing {
+                    Some(idx) => idx,
+                    None => {
+                        let stub = ServiceInfo {
+                            service_type: Name::Borrowed(service_type),
+                            instance_name: Name::Borrowed(ptr.name.clone()),
+                            hostname: Name::Borrowed(Label::default()),
+                            addresses: Vec::new(),
+                            port: 0,
+                            txt: Txt::new(),
+                            subtypes: Vec::new(),
+                        };
+                        if output.push(stub).is_err() {
+                            continue;
+                        }
+                        output.len() - 1
+                    }
+                };
+
+                if let Some(subtype) = subtype {
+                    output[idx].push_subtype(subtype);
+                }
             }
         }
 
         // Step 2: Process SRV records and merge data
-        for answer in answers {
+        for answer in answers.clone() {
             if let Record::SRV(srv) = &answer.record {
                 for stub in output.iter_mut() {
                     if stub.instance_name == answer.name {
-                        stub.hostname = srv.target.clone();
+                        stub.hostname = Name::Borrowed(srv.target.clone());
                         stub.port = srv.port;
                     }
                 }
             }
         }
 
-        // Step 3: Process A and AAAA records and merge data
-        for answer in answers {
-            match &answer.record {
-                Record::A(a) => {
-                    for stub in output.iter_mut() {
-                        if stub.hostname == answer.name {
-                            stub.ip_address = IpAddr::V4(a.address);
-                            stub.netmask = NETMASK_FULL_V4;
-                        }
+        // Step 3: Process TXT records and merge data
+        for answer in answers.clone() {
+            if let Record::TXT(txt) = &answer.record {
+                for stub in output.iter_mut() {
+                    if stub.instance_name == answer.name {
+                        stub.txt = Txt::from_rdata(txt.data);
                     }
                 }
-                Record::AAAA(aaaa) => {
-                    for stub in output.iter_mut() {
-                        if stub.hostname == answer.name {
-                            stub.ip_address = IpAddr::V6(aaaa.address);
-                            stub.netmask = NETMASK_FULL_V6;
-                        }
-                    }
+            }
+        }
+
+        // Step 4: Process A and AAAA records and merge data
+        for answer in answers.clone() {
+            let (ip, netmask) = match &answer.record {
+                Record::A(a) => (IpAddr::V4(a.address), NETMASK_FULL_V4),
+                Record::AAAA(aaaa) => (IpAddr::V6(aaaa.address), NETMASK_FULL_V6),
+                _ => continue,
+            };
+
+            for stub in output.iter_mut() {
+                if stub.hostname == answer.name {
+                    stub.add_address(ip, netmask);
                 }
-                _ => {}
             }
         }
 
@@ -216,37 +634,79 @@ impl<'a, const LLEN: usize> ServiceInfo<'a, LLEN> {
             !stub.service_type.is_empty()
                 && !stub.instance_name.is_empty()
                 && !stub.hostname.is_empty()
-                && stub.ip_address != DEFAULT_ADDR
+                && !stub.addresses.is_empty()
                 && stub.port != 0
         });
     }
 
+    /// All records of the service, with every address.
+    #[cfg(all(feature = "std", test))]
     pub(crate) fn as_answers(
         &'a self,
-        aclass: QClass,
+        cache_flush: bool,
+    ) -> impl Iterator<Item = Answer<'a, LLEN>> + 'a {
+        self.ptr_answers()
+            .chain([self.srv_answer(cache_flush), self.txt_answer(cache_flush)])
+            .chain(self.ip_answers(cache_flush))
+    }
+
+    /// All records of the service, but only with the addresses on the same network
+    /// as `local`.
+    pub(crate) fn as_answers_from(
+        &'a self,
+        cache_flush: bool,
+        local: IpAddr,
     ) -> impl Iterator<Item = Answer<'a, LLEN>> + 'a {
-        [
-            self.ptr_answer(aclass),
-            self.srv_answer(aclass),
-            self.txt_answer(aclass),
-            self.ip_answer(aclass),
-        ]
-        .into_iter()
+        let addresses = self
+            .networks()
+            .filter(move |(ip, netmask)| is_same_network(*ip, *netmask, local))
+            .map(move |(ip, _)| self.ip_answer(ip, cache_flush));
+
+        self.ptr_answers()
+            .chain([self.srv_answer(cache_flush), self.txt_answer(cache_flush)])
+            .chain(addresses)
+    }
+}
+
+/// The service type of a subtype name, i.e. `_http._tcp.local` for
+/// `_printer._sub._http._tcp.local` (RFC 6763 7.1).
+fn subtype_parent<'a, const LLEN: usize>(name: &Label<'a, LLEN>) -> Option<Label<'a, LLEN>> {
+    let mut segments = name.iter();
+    segments.next()?;
+    if !segments.next()?.eq_ignore_ascii_case("_sub") {
+        return None;
     }
+
+    let mut parent = name.clone();
+    parent.pop_front();
+    parent.pop_front();
+    Some(parent)
+}
+
+/// Split `name (2)` into `name` and `2`. Names without suffix count as 1.
+fn split_rename_suffix(s: &str) -> (&str, u32) {
+    let parsed = s
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once(" ("))
+        .and_then(|(base, n)| Some((base, n.parse::<u32>().ok()?)));
+
+    parsed.unwrap_or((s, 1))
 }
 
 #[cfg(feature = "defmt")]
-impl<const LLEN: usize> defmt::Format for ServiceInfo<'_, LLEN> {
+impl<const LLEN: usize, const SUBLEN: usize> defmt::Format for ServiceInfo<'_, LLEN, SUBLEN> {
     fn format(&self, fmt: defmt::Formatter) {
         use crate::format::FormatIpAddr;
         defmt::write!(
             fmt,
-            "ServiceInfo {{ service_type: {}, instance_name: {}, hostname: {}, ip_address: {}, port: {} }}",
+            "ServiceInfo {{ service_type: {}, instance_name: {}, hostname: {}, ip_address: {}, port: {}, txt: {}, subtypes: {} }}",
             self.service_type,
             self.instance_name,
             self.hostname,
-            FormatIpAddr(self.ip_address),
-            self.port
+            FormatIpAddr(self.ip_address()),
+            self.port,
+            self.txt,
+            self.subtypes
         );
     }
 }
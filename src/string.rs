@@ -0,0 +1,58 @@
+use core::fmt;
+use core::ops::Deref;
+
+#[cfg(not(feature = "alloc"))]
+type Inner<const N: usize> = heapless::String<N>;
+#[cfg(feature = "alloc")]
+type Inner<const N: usize> = alloc::string::String;
+
+#[derive(Default, Clone, PartialEq, Eq)]
+pub struct String<const N: usize> {
+    inner: Inner<N>,
+}
+
+impl<const N: usize> String<N> {
+    pub fn new() -> Self {
+        Self {
+            inner: Inner::new(),
+        }
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    pub fn push_str(&mut self, s: &str) -> Result<(), ()> {
+        self.inner.push_str(s)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn push_str(&mut self, s: &str) -> Result<(), ()> {
+        self.inner.push_str(s);
+        Ok(())
+    }
+}
+
+impl<const N: usize> fmt::Write for String<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s).map_err(|_| fmt::Error)
+    }
+}
+
+impl<const N: usize> fmt::Debug for String<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.inner.as_str(), f)
+    }
+}
+
+impl<const N: usize> Deref for String<N> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const N: usize> defmt::Format for String<N> {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{=str}", self.inner.as_str());
+    }
+}
@@ -0,0 +1,214 @@
+use core::fmt;
+
+use crate::dns::TxtIter;
+use crate::vec::Vec;
+
+/// Max size of the TXT record data. Only used if not **alloc**.
+const MAX_TXT_LEN: usize = 255;
+
+/// Key/value entries for the TXT record of a service (RFC 6763 6).
+///
+/// ```
+/// use opslag::{ServiceInfo, Txt};
+///
+/// let mut txt = Txt::new();
+/// txt.insert("version", Some(b"1.2"));
+/// txt.insert("secure", None);
+///
+/// let info = ServiceInfo::<4>::new(
+///    "_my-service._udp.local",
+///    "instance01",
+///    "nugget.local",
+///    [192, 168, 0, 3],
+///    [255, 255, 255, 0],
+///    1234,
+/// )
+/// .with_txt(txt);
+///
+/// assert_eq!(info.txt().get("version"), Some(Some(&b"1.2"[..])));
+/// ```
+#[derive(Default, Clone, PartialEq, Eq)]
+pub struct Txt {
+    /// Entries encoded as length prefixed strings.
+    data: Vec<u8, MAX_TXT_LEN>,
+}
+
+impl Txt {
+    /// Creates an empty set of entries.
+    pub fn new() -> Self {
+        Txt { data: Vec::new() }
+    }
+
+    /// Add an entry.
+    ///
+    /// A `None` value is a boolean attribute, i.e. `key` rather than `key=`. The key
+    /// must be printable US-ASCII without `=`, and the entry at most 255 bytes. Keys
+    /// are case insensitive and only the first occurrence of a key counts.
+    ///
+    /// Returns `false` if the key is not valid, or the entry doesn't fit. Without
+    /// **alloc**, all entries must fit in 255 bytes.
+    pub fn insert(&mut self, key: &str, value: Option<&[u8]>) -> bool {
+        let is_valid_key =
+            !key.is_empty() && key.bytes().all(|b| (0x20..=0x7e).contains(&b) && b != b'=');
+
+        if !is_valid_key {
+            return false;
+        }
+
+        let len = key.len() + value.map(|v| v.len() + 1).unwrap_or(0);
+
+        if len > u8::MAX as usize {
+            return false;
+        }
+
+        let before = self.data.len();
+
+        let fits = self.push(&[len as u8])
+            && self.push(key.as_bytes())
+            && value
+                .map(|v| self.push(b"=") && self.push(v))
+                .unwrap_or(true);
+
+        if !fits {
+            self.data.truncate(before);
+        }
+
+        fits
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> bool {
+        bytes.iter().all(|b| self.data.push(*b).is_ok())
+    }
+
+    /// Look up the value for a key.
+    ///
+    /// Returns `Some(None)` if the key is present without value.
+    pub fn get(&self, key: &str) -> Option<Option<&[u8]>> {
+        self.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// Iterate the entries.
+    pub fn iter(&self) -> TxtIter<'_> {
+        TxtIter::new(&self.data)
+    }
+
+    /// Whether there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Copy the entries of a received TXT record. Entries that don't fit are dropped.
+    pub(crate) fn from_rdata(data: &[u8]) -> Self {
+        let mut txt = Txt::new();
+
+        for (key, value) in TxtIter::new(data) {
+            if !txt.insert(key, value) {
+                debug!("TXT entry dropped: {}", key);
+            }
+        }
+
+        txt
+    }
+
+    /// The rdata to send. An empty record is a single empty string (RFC 6763 6.1).
+    pub(crate) fn rdata(&self) -> &[u8] {
+        if self.data.is_empty() {
+            &[0]
+        } else {
+            &self.data
+        }
+    }
+}
+
+impl fmt::Debug for Txt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|(k, v)| (k, v.map(DebugValue))))
+            .finish()
+    }
+}
+
+/// Values as strings when they are UTF-8, otherwise as bytes.
+struct DebugValue<'a>(&'a [u8]);
+
+impl fmt::Debug for DebugValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match core::str::from_utf8(self.0) {
+            Ok(s) => fmt::Debug::fmt(s, f),
+            Err(_) => fmt::Debug::fmt(self.0, f),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Txt {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{{");
+        let mut first = true;
+        for (key, value) in self.iter() {
+            if !first {
+                defmt::write!(fmt, ", ");
+            }
+            first = false;
+            match value {
+                Some(value) => defmt::write!(fmt, "{=str}: {=[u8]}", key, value),
+                None => defmt::write!(fmt, "{=str}", key),
+            }
+        }
+        defmt::write!(fmt, "}}");
+    }
+}
+
+#[cfg(all(feature = "std", test))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_and_iterate() {
+        let mut txt = Txt::new();
+        assert!(txt.insert("version", Some(b"1.2")));
+        assert!(txt.insert("secure", None));
+        assert!(txt.insert("empty", Some(b"")));
+        assert!(txt.insert("bin", Some(&[0, 0xff])));
+
+        assert_eq!(
+            txt.rdata(),
+            b"\x0bversion=1.2\x06secure\x06empty=\x06bin=\x00\xff"
+        );
+
+        let entries: std::vec::Vec<_> = txt.iter().collect();
+        assert_eq!(
+            entries,
+            [
+                ("version", Some(&b"1.2"[..])),
+                ("secure", None),
+                ("empty", Some(&b""[..])),
+                ("bin", Some(&[0, 0xff][..])),
+            ]
+        );
+
+        assert_eq!(txt.get("VERSION"), Some(Some(&b"1.2"[..])));
+        assert_eq!(txt.get("secure"), Some(None));
+        assert_eq!(txt.get("missing"), None);
+    }
+
+    #[test]
+    fn invalid_keys() {
+        let mut txt = Txt::new();
+        assert!(!txt.insert("", None));
+        assert!(!txt.insert("a=b", None));
+        assert!(!txt.insert("tab\t", None));
+        assert!(!txt.insert("long", Some(&[b'x'; 255])));
+        assert!(txt.is_empty());
+        assert_eq!(txt.rdata(), b"\0");
+    }
+
+    #[test]
+    fn parse_skips_empty_and_truncated() {
+        let data = b"\x00\x03a=1\x00\x04b\xff=2\x01c\x0atruncated";
+        let entries: std::vec::Vec<_> = TxtIter::new(data).collect();
+        assert_eq!(entries, [("a", Some(&b"1"[..])), ("c", None)]);
+    }
+}
@@ -28,6 +28,11 @@ impl<T, const N: usize> Vec<T, N> {
         self.inner.insert(index, element).map_err(|_| ())
     }
 
+    #[cfg(not(feature = "alloc"))]
+    pub fn is_full(&self) -> bool {
+        self.inner.is_full()
+    }
+
     #[cfg(feature = "alloc")]
     pub fn push(&mut self, element: T) -> Result<(), ()> {
         self.inner.push(element);
@@ -40,6 +45,11 @@ impl<T, const N: usize> Vec<T, N> {
         Ok(())
     }
 
+    #[cfg(feature = "alloc")]
+    pub fn is_full(&self) -> bool {
+        false
+    }
+
     pub fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
         self.inner.extend(iter)
     }
@@ -9,6 +9,7 @@ pub struct Writer<'a, 'b, const LK: usize = 10> {
     output: &'b mut [u8],
     position: usize,
     overflow: bool,
+    needed: usize,
     lookup: Vec<LabelOffset<'a>, LK>,
 }
 
@@ -18,6 +19,7 @@ impl<'a, 'b, const LK: usize> Writer<'a, 'b, LK> {
             output: buffer,
             position: 0,
             overflow: false,
+            needed: 0,
             lookup: Vec::new(),
         }
     }
@@ -34,7 +36,14 @@ impl<'a, 'b, const LK: usize> Writer<'a, 'b, LK> {
         self.overflow
     }
 
+    /// Buffer size needed to hold everything written, which is more than
+    /// [`len()`][Self::len] on overflow.
+    pub fn needed(&self) -> usize {
+        self.needed
+    }
+
     pub(crate) fn write(&mut self, data: &[u8]) {
+        self.needed += data.len();
         if self.overflow {
             return;
         }
@@ -48,6 +57,7 @@ impl<'a, 'b, const LK: usize> Writer<'a, 'b, LK> {
     }
 
     pub(crate) fn write_u8(&mut self, b: u8) {
+        self.needed += 1;
         if self.overflow {
             return;
         }
@@ -60,6 +70,7 @@ impl<'a, 'b, const LK: usize> Writer<'a, 'b, LK> {
     }
 
     pub(crate) fn inc(&mut self, v: usize) {
+        self.needed += v;
         if self.overflow {
             return;
         }
@@ -84,13 +95,15 @@ impl<'a, 'b, const LK: usize> Writer<'a, 'b, LK> {
         let r = Reservation {
             start: self.position,
             len,
+            needed: self.needed,
         };
         self.inc(len);
         r
     }
 
     pub(crate) fn distance_from_reservation(&self, r: &Reservation) -> usize {
-        self.position - r.start
+        // The position stops moving on overflow, but what is needed keeps counting.
+        self.needed - r.needed
     }
 
     pub(crate) fn write_reservation(&mut self, r: Reservation, data: &[u8]) {
@@ -104,6 +117,7 @@ impl<'a, 'b, const LK: usize> Writer<'a, 'b, LK> {
 pub(crate) struct Reservation {
     start: usize,
     len: usize,
+    needed: usize,
 }
 
 impl<const LK: usize> Deref for Writer<'_, '_, LK> {